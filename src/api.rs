@@ -1,26 +1,30 @@
+use crate::blockchain::{AccountState, Blockchain, account_key};
 use crate::config::ApiConfig;
 use crate::mempool::{Mempool, MempoolError};
-use crate::block::Transaction;
+use crate::block::{Receipt, Transaction};
+use crate::merkle::{self, MerkleProof};
 use crate::p2p::ChainMessage;
+use crate::rpc;
 
 use axum::{
-    routing::post, http::StatusCode, Json, Router, extract::State,
+    routing::{get, post}, http::StatusCode, Json, Router, extract::{Path, State},
 };
+use ethers_core::types::Address;
+use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc;
-use tower_governor::{
-    governor::{Governor, GovernorConfigBuilder},
-};
+use tower_governor::{governor::GovernorConfigBuilder, GovernorLayer};
 
 #[derive(Clone)]
 pub struct AppState {
+    pub blockchain: Arc<Mutex<Blockchain>>,
     pub mempool: Arc<Mutex<Mempool>>,
     pub p2p_tx: mpsc::Sender<ChainMessage>,
 }
 
 pub async fn run_api(config: ApiConfig, state: AppState) {
-    let governor_config = Box::new(
+    let governor_config = Arc::new(
         GovernorConfigBuilder::default()
             .per_second(5)
             .burst_size(10)
@@ -30,13 +34,18 @@ pub async fn run_api(config: ApiConfig, state: AppState) {
 
     let app = Router::new()
         .route("/transaction", post(handle_transaction))
+        .route("/receipt/:hash", get(handle_get_receipt))
+        .route("/proof/transaction/:hash", get(handle_transaction_proof))
+        .route("/proof/account/:address", get(handle_account_proof))
+        .route("/proof/verify", post(handle_verify_proof))
+        .route("/", post(rpc::handle_rpc))
         .with_state(state)
-        .layer(tower::ServiceBuilder::new().layer(Governor::new(&governor_config)));
+        .layer(GovernorLayer { config: governor_config });
 
     let addr: SocketAddr = config.listen_address.parse().expect("Invalid API listen address");
     println!("[API] Server with rate limiting listening on {}", addr);
     
-    if let Err(e) = axum::serve(tokio::net::TcpListener::bind(addr).await.unwrap(), app.into_make_service()).await {
+    if let Err(e) = axum::serve(tokio::net::TcpListener::bind(addr).await.unwrap(), app.into_make_service_with_connect_info::<SocketAddr>()).await {
         eprintln!("[API] Server error: {}", e);
     }
 }
@@ -45,9 +54,10 @@ async fn handle_transaction(
     State(state): State<AppState>,
     Json(tx): Json<Transaction>,
 ) -> (StatusCode, String) {
+    let chain = state.blockchain.lock().unwrap();
     let mut mempool = state.mempool.lock().unwrap();
     
-    match mempool.add_transaction(tx.clone()) {
+    match mempool.add_local_transaction(tx.clone(), &chain) {
         Ok(_) => {
             println!("[API] Accepted new transaction: {}", tx.hash);
             if state.p2p_tx.try_send(ChainMessage::Transaction(tx)).is_err() {
@@ -55,11 +65,84 @@ async fn handle_transaction(
             }
             (StatusCode::OK, "Transaction accepted".to_string())
         }
-        Err(MempoolError::PoolFull) => {
-            (StatusCode::SERVICE_UNAVAILABLE, "Mempool is full".to_string())
-        }
-        Err(MempoolError::AlreadyExists) => {
-            (StatusCode::BAD_REQUEST, "Transaction already in mempool".to_string())
-        }
+        Err(e @ MempoolError::Underpriced) => (StatusCode::SERVICE_UNAVAILABLE, e.to_string()),
+        Err(e @ MempoolError::AlreadyExists) => (StatusCode::CONFLICT, e.to_string()),
+        Err(e @ MempoolError::Oversized { .. }) => (StatusCode::PAYLOAD_TOO_LARGE, e.to_string()),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()),
     }
 }
+
+async fn handle_get_receipt(
+    State(state): State<AppState>,
+    Path(hash): Path<String>,
+) -> Result<Json<Receipt>, (StatusCode, String)> {
+    let chain = state.blockchain.lock().unwrap();
+    chain.receipt(hash.trim_start_matches("0x"))
+        .cloned()
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "Receipt not found".to_string()))
+}
+
+#[derive(Serialize)]
+struct TransactionProofResponse {
+    block_id: u64,
+    transactions_hash: String,
+    proof: MerkleProof,
+}
+
+/// Proves that a mined transaction is included in its block's `transactions_hash`.
+async fn handle_transaction_proof(
+    State(state): State<AppState>,
+    Path(hash): Path<String>,
+) -> Result<Json<TransactionProofResponse>, (StatusCode, String)> {
+    let chain = state.blockchain.lock().unwrap();
+    let (block, tx) = chain.find_transaction(hash.trim_start_matches("0x"))
+        .ok_or((StatusCode::NOT_FOUND, "Transaction not found".to_string()))?;
+    let proof = block.inclusion_proof(&tx.hash)
+        .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Failed to build proof".to_string()))?;
+
+    Ok(Json(TransactionProofResponse {
+        block_id: block.header.id,
+        transactions_hash: block.header.transactions_hash.clone(),
+        proof,
+    }))
+}
+
+#[derive(Serialize)]
+struct AccountProofResponse {
+    block_id: u64,
+    state_root: String,
+    account: AccountState,
+    proof: MerkleProof,
+}
+
+/// Proves that an account is part of the state root of the latest block.
+async fn handle_account_proof(
+    State(state): State<AppState>,
+    Path(address): Path<Address>,
+) -> Result<Json<AccountProofResponse>, (StatusCode, String)> {
+    let chain = state.blockchain.lock().unwrap();
+    let key = account_key(&address.0);
+    let account = chain.state.get(&key)
+        .ok_or((StatusCode::NOT_FOUND, "Account not found".to_string()))?;
+    let proof = chain.account_proof(&key)
+        .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Failed to build proof".to_string()))?;
+    let head = chain.blocks.last().expect("Chain always contains a genesis block");
+
+    Ok(Json(AccountProofResponse {
+        block_id: head.header.id,
+        state_root: head.header.state_root.clone(),
+        account: account.clone(),
+        proof,
+    }))
+}
+
+#[derive(Deserialize)]
+struct VerifyProofRequest {
+    root: String,
+    proof: MerkleProof,
+}
+
+async fn handle_verify_proof(Json(request): Json<VerifyProofRequest>) -> Json<bool> {
+    Json(merkle::verify_proof(request.root.trim_start_matches("0x"), &request.proof))
+}
@@ -2,10 +2,14 @@ use serde::{Serialize, Deserialize};
 use std::hash::{Hash, Hasher};
 use sha2::{Sha256, Digest};
 use ed25519_dalek::Signature;
+use crate::errors::NodeError;
+use crate::merkle::{self, hash_leaf, merkle_root, MerkleHash, MerkleProof};
 use crate::wallet::Wallet;
-use ethers_core::types::{Address, U256};
+use ethers_core::types::{Address, H256, U256, U64};
+use ethers_core::types::transaction::eip2718::TypedTransaction;
+use ethers_core::utils::{keccak256, rlp::Rlp};
 
-// An enum to represent the two main types of actions on the chain.
+// An enum to represent the types of actions on the chain.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TransactionAction {
     // A simple transfer of the native token
@@ -19,35 +23,405 @@ pub enum TransactionAction {
         data: Vec<u8>,
         value: U256,
     },
+    // A raw RLP-encoded, secp256k1-signed Ethereum transaction (legacy EIP-155 or typed),
+    // as produced by MetaMask, ethers.js and similar tooling.
+    Ethereum {
+        raw: Vec<u8>,
+    },
 }
 
+/// The chain id of the Kosher Chain mainnet, used unless a genesis file sets another one.
+/// The chain id is part of every transaction's signing payload, so a transaction signed
+/// for another network cannot be replayed here.
+pub const KOSHER_CHAIN_ID: u64 = 7_777;
+
+/// A transaction on the Kosher Chain.
+/// `sender` is the account address derived from `public_key` (see `Wallet::address_from_public_key`),
+/// and `signature` is the ed25519 signature of that key over `hash`.
+/// For `TransactionAction::Ethereum` the raw transaction carries its own secp256k1 signature:
+/// `sender` is recovered from it, and `public_key` and `signature` are unused.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
     pub sender: Address,
+    pub public_key: String,
     pub action: TransactionAction,
     pub nonce: u64,
+    pub chain_id: u64,
+    /// The maximum gas this transaction may consume.
+    pub gas_limit: u64,
+    /// The price paid per unit of gas, deducted from the sender and paid to the block's validator.
+    pub max_fee_per_gas: U256,
     pub hash: String,
     pub signature: Signature,
 }
 
-// ... Implementations for Hash, PartialEq, Eq ...
+/// The fields of a transaction covered by its hash, in canonical order.
+/// Field order is fixed by the struct definition, so the JSON encoding is deterministic.
+#[derive(Serialize)]
+struct SigningPayload<'a> {
+    chain_id: u64,
+    sender: &'a Address,
+    public_key: &'a str,
+    nonce: u64,
+    gas_limit: u64,
+    max_fee_per_gas: &'a U256,
+    action: &'a TransactionAction,
+}
+
+impl Transaction {
+    /// Builds a transaction from `wallet`'s account for the chain `chain_id`, then hashes and signs it.
+    pub fn new_signed(wallet: &Wallet, chain_id: u64, action: TransactionAction, nonce: u64, gas_limit: u64, max_fee_per_gas: U256) -> Self {
+        let mut tx = Self {
+            sender: wallet.address(),
+            public_key: wallet.public_key_hex(),
+            action,
+            nonce,
+            chain_id,
+            gas_limit,
+            max_fee_per_gas,
+            hash: String::new(),
+            signature: Signature::from_bytes(&[0; 64]),
+        };
+        tx.hash = tx.compute_hash();
+        tx.signature = wallet.sign(tx.hash.as_bytes());
+        tx
+    }
+
+    /// Wraps a raw signed Ethereum transaction, recovering its sender.
+    /// Only EIP-155 transactions signed for `chain_id` are accepted.
+    pub fn from_ethereum_raw(raw: Vec<u8>, chain_id: u64) -> Result<Self, NodeError> {
+        let (typed, sender) = decode_ethereum_transaction(&raw, chain_id)?;
+        let nonce = typed.nonce().map(|nonce| u64_field(nonce, "nonce")).transpose()?.unwrap_or_default();
+        let gas_limit = typed.gas().map(|gas| u64_field(gas, "gas limit")).transpose()?.unwrap_or_default();
+        let max_fee_per_gas = typed.gas_price().unwrap_or_default();
+
+        let mut tx = Self {
+            sender,
+            public_key: String::new(),
+            action: TransactionAction::Ethereum { raw },
+            nonce,
+            chain_id,
+            gas_limit,
+            max_fee_per_gas,
+            hash: String::new(),
+            signature: Signature::from_bytes(&[0; 64]),
+        };
+        tx.hash = tx.compute_hash();
+        Ok(tx)
+    }
+
+    /// Returns the canonical encoding of the transaction body that is hashed and signed.
+    pub fn signing_payload(&self) -> Vec<u8> {
+        let payload = SigningPayload {
+            chain_id: self.chain_id,
+            sender: &self.sender,
+            public_key: &self.public_key,
+            nonce: self.nonce,
+            gas_limit: self.gas_limit,
+            max_fee_per_gas: &self.max_fee_per_gas,
+            action: &self.action,
+        };
+        serde_json::to_vec(&payload).expect("Transaction payload is always serializable")
+    }
+
+    /// Computes the transaction hash from its body, independent of the `hash` field.
+    /// Ethereum transactions keep their standard keccak256 hash so tooling can track them.
+    pub fn compute_hash(&self) -> String {
+        match &self.action {
+            TransactionAction::Ethereum { raw } => hex::encode(keccak256(raw)),
+            _ => hex::encode(Sha256::digest(self.signing_payload())),
+        }
+    }
+
+    /// Returns true if the stored `hash` matches the hash recomputed from the body.
+    pub fn is_hash_valid(&self) -> bool {
+        self.hash == self.compute_hash()
+    }
+
+    /// The amount of the native token sent along with the transaction.
+    pub fn value(&self) -> U256 {
+        match &self.action {
+            TransactionAction::Transfer { amount, .. } => *amount,
+            TransactionAction::Call { value, .. } => *value,
+            TransactionAction::Ethereum { raw } => decode_ethereum_transaction(raw, self.chain_id).ok()
+                .and_then(|(typed, _)| typed.value().copied())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// The most the sender can be charged: the value plus the full gas limit at `max_fee_per_gas`.
+    pub fn max_cost(&self) -> U256 {
+        U256::from(self.gas_limit).saturating_mul(self.max_fee_per_gas).saturating_add(self.value())
+    }
+}
+
+/// Decodes a raw signed Ethereum transaction and recovers its sender.
+/// Rejects transactions without an EIP-155 chain id or signed for a chain other than `chain_id`.
+pub fn decode_ethereum_transaction(raw: &[u8], chain_id: u64) -> Result<(TypedTransaction, Address), NodeError> {
+    let (typed, signature) = TypedTransaction::decode_signed(&Rlp::new(raw))
+        .map_err(|e| NodeError::Blockchain(format!("Malformed Ethereum transaction: {}", e)))?;
+
+    match typed.chain_id() {
+        Some(signed_for) if signed_for == U64::from(chain_id) => {}
+        Some(signed_for) => return Err(NodeError::Blockchain(format!("Ethereum transaction signed for chain {}", signed_for))),
+        None => return Err(NodeError::Blockchain("Ethereum transaction is missing an EIP-155 chain id".into())),
+    }
+
+    // Nonces and gas limits are 64-bit on this chain; larger values can never be valid.
+    if let Some(nonce) = typed.nonce() {
+        u64_field(nonce, "nonce")?;
+    }
+    if let Some(gas) = typed.gas() {
+        u64_field(gas, "gas limit")?;
+    }
+
+    let sender = signature.recover(typed.sighash())
+        .map_err(|e| NodeError::Blockchain(format!("Failed to recover Ethereum sender: {}", e)))?;
+    Ok((typed, sender))
+}
+
+/// Converts a decoded Ethereum transaction field to a u64, rejecting values that do not fit.
+fn u64_field(value: &U256, field: &str) -> Result<u64, NodeError> {
+    if *value > U256::from(u64::MAX) {
+        return Err(NodeError::Blockchain(format!("Ethereum transaction {} {} is out of range", field, value)));
+    }
+    Ok(value.as_u64())
+}
+
+// Transactions are identified by their hash.
+impl Hash for Transaction {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.hash.hash(state);
+    }
+}
+
+impl PartialEq for Transaction {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash
+    }
+}
+
+impl Eq for Transaction {}
 
 impl Block {
-    // ... calculate_header_hash and hash_transactions remain the same ...
+    /// Hashes the block header. This is the message signed by the validator.
+    pub fn calculate_header_hash(&self) -> String {
+        Self::header_hash(&self.header)
+    }
+
+    /// Hashes a header on its own, e.g. one received without its block during sync.
+    pub fn header_hash(header: &BlockHeader) -> String {
+        let data = serde_json::to_vec(header).expect("Block header is always serializable");
+        hex::encode(Sha256::digest(data))
+    }
+
+    /// Computes the Merkle root over the block's transactions, in block order.
+    /// Each leaf is the hash of a transaction's hex-encoded `hash`.
+    pub fn hash_transactions(transactions: &[Transaction]) -> String {
+        hex::encode(merkle_root(&Self::transaction_leaves(transactions)))
+    }
+
+    fn transaction_leaves(transactions: &[Transaction]) -> Vec<MerkleHash> {
+        transactions.iter().map(|tx| hash_leaf(tx.hash.as_bytes())).collect()
+    }
+
+    /// Proves that a transaction is included in this block's `transactions_hash`.
+    pub fn inclusion_proof(&self, tx_hash: &str) -> Option<MerkleProof> {
+        let index = self.transactions.iter().position(|tx| tx.hash == tx_hash)?;
+        merkle::inclusion_proof(&Self::transaction_leaves(&self.transactions), index)
+    }
+
+    /// Hashes the ordered list of receipts produced by executing a block.
+    pub fn hash_receipts(receipts: &[Receipt]) -> String {
+        let mut hasher = Sha256::new();
+        for receipt in receipts {
+            hasher.update(serde_json::to_vec(receipt).expect("Receipt is always serializable"));
+        }
+        hex::encode(hasher.finalize())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockHeader {
     pub id: u64,
+    /// The consensus round this block was proposed in. Round 0 belongs to the
+    /// scheduled proposer; each view change moves the turn to the next validator.
+    pub round: u32,
     pub timestamp: i64,
     pub previous_hash: String,
     pub validator_pubkey: String,
     pub transactions_hash: String,
+    pub receipts_root: String,
+    /// The state root after executing this block. See `Blockchain::state_root`.
+    pub state_root: String,
+}
+
+/// A validator's precommit for a block: its signature over the block's height, the
+/// round it precommitted in and the header hash. See `consensus::precommit_message`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommitSignature {
+    pub validator_pubkey: String,
+    #[serde(default)]
+    pub round: u32,
+    pub signature: Signature,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
     pub header: BlockHeader,
     pub transactions: Vec<Transaction>,
+    /// The proposer's signature over the header hash.
     pub signature: Signature,
+    /// Precommits from more than two thirds of the validator set in a single round,
+    /// collected after the proposal. A block is only final once its commit is complete.
+    #[serde(default)]
+    pub commit: Vec<CommitSignature>,
+}
+
+/// An event emitted by a contract during execution.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Log {
+    pub address: Address,
+    pub topics: Vec<H256>,
+    pub data: Vec<u8>,
+}
+
+/// The outcome of executing a transaction, stored alongside its block.
+/// Reverted and halted transactions are still included in the block, since
+/// they consume the sender's nonce and pay for their gas.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Receipt {
+    pub tx_hash: String,
+    pub block_id: u64,
+    pub index: usize,
+    pub success: bool,
+    pub gas_used: u64,
+    pub logs: Vec<Log>,
+    /// The address of the contract created by this transaction, if any.
+    pub contract_address: Option<Address>,
+}
+
+// --- Unit Tests ---
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use ethers_core::k256::ecdsa::SigningKey;
+    use ethers_core::types::{Signature as EthSignature, TransactionRequest};
+    use ethers_core::utils::secret_key_to_address;
+
+    fn sample_transaction() -> Transaction {
+        let wallet = Wallet::new();
+        let action = TransactionAction::Transfer {
+            recipient: Address::repeat_byte(0x22),
+            amount: U256::from(1_000u64),
+        };
+        Transaction::new_signed(&wallet, KOSHER_CHAIN_ID, action, 0, 21_000, U256::from(1u64))
+    }
+
+    /// Signs `request` for `chain_id` as an Ethereum wallet would (EIP-155 legacy
+    /// transaction), returning the raw transaction and the signer's address.
+    pub(crate) fn sign_ethereum(request: TransactionRequest, chain_id: Option<u64>) -> (Vec<u8>, Address) {
+        let key = SigningKey::from_slice(&[0x11; 32]).unwrap();
+        let mut typed: TypedTransaction = request.into();
+        if let Some(chain_id) = chain_id {
+            typed.set_chain_id(chain_id);
+        }
+        let (signature, recovery_id) = key.sign_prehash_recoverable(typed.sighash().as_bytes()).unwrap();
+        let v = recovery_id.to_byte() as u64 + chain_id.map_or(27, |chain_id| 35 + 2 * chain_id);
+        let signature = EthSignature {
+            r: U256::from_big_endian(&signature.r().to_bytes()),
+            s: U256::from_big_endian(&signature.s().to_bytes()),
+            v,
+        };
+        (typed.rlp_signed(&signature).to_vec(), secret_key_to_address(&key))
+    }
+
+    fn eth_transfer() -> TransactionRequest {
+        TransactionRequest::new().to(Address::repeat_byte(0x22)).value(1_000).nonce(3).gas(21_000).gas_price(2)
+    }
+
+    #[test]
+    fn test_ethereum_transaction_decodes_and_recovers_sender() {
+        let (raw, signer) = sign_ethereum(eth_transfer(), Some(KOSHER_CHAIN_ID));
+        let (typed, sender) = decode_ethereum_transaction(&raw, KOSHER_CHAIN_ID).unwrap();
+        assert_eq!(sender, signer);
+        assert_eq!(typed.to_addr(), Some(&Address::repeat_byte(0x22)));
+
+        let tx = Transaction::from_ethereum_raw(raw, KOSHER_CHAIN_ID).unwrap();
+        assert_eq!((tx.sender, tx.nonce, tx.gas_limit, tx.max_fee_per_gas), (signer, 3, 21_000, U256::from(2)));
+        assert_eq!(tx.value(), U256::from(1_000));
+        assert!(tx.is_hash_valid());
+    }
+
+    #[test]
+    fn test_ethereum_transaction_for_another_chain_is_rejected() {
+        let (raw, _) = sign_ethereum(eth_transfer(), Some(1));
+        let err = decode_ethereum_transaction(&raw, KOSHER_CHAIN_ID).unwrap_err();
+        assert!(err.to_string().contains("signed for chain 1"));
+
+        // Pre-EIP-155 signatures can be replayed on any chain.
+        let (raw, _) = sign_ethereum(eth_transfer(), None);
+        let err = decode_ethereum_transaction(&raw, KOSHER_CHAIN_ID).unwrap_err();
+        assert!(err.to_string().contains("EIP-155"));
+    }
+
+    #[test]
+    fn test_ethereum_transaction_with_oversized_fields_is_rejected() {
+        let too_large = U256::from(u64::MAX) + 1;
+        for request in [eth_transfer().nonce(too_large), eth_transfer().gas(too_large)] {
+            let (raw, _) = sign_ethereum(request, Some(KOSHER_CHAIN_ID));
+            let err = decode_ethereum_transaction(&raw, KOSHER_CHAIN_ID).unwrap_err();
+            assert!(err.to_string().contains("out of range"));
+            assert!(Transaction::from_ethereum_raw(raw, KOSHER_CHAIN_ID).is_err());
+        }
+        assert!(decode_ethereum_transaction(&[0xde, 0xad], KOSHER_CHAIN_ID).is_err());
+    }
+
+    #[test]
+    fn test_compute_hash_is_deterministic() {
+        let tx = sample_transaction();
+        assert_eq!(tx.compute_hash(), tx.clone().compute_hash());
+        assert!(tx.is_hash_valid());
+        assert!(Wallet::verify_signature(&tx.public_key, tx.hash.as_bytes(), &tx.signature));
+    }
+
+    #[test]
+    fn test_transaction_inclusion_proof() {
+        let transactions: Vec<Transaction> = (0..3).map(|_| sample_transaction()).collect();
+        let root = Block::hash_transactions(&transactions);
+        let block = Block {
+            header: BlockHeader {
+                id: 1,
+                round: 0,
+                timestamp: 0,
+                previous_hash: "0".repeat(64),
+                validator_pubkey: String::new(),
+                transactions_hash: root.clone(),
+                receipts_root: String::new(),
+                state_root: String::new(),
+            },
+            transactions: transactions.clone(),
+            signature: Signature::from_bytes(&[0; 64]),
+            commit: vec![],
+        };
+
+        let proof = block.inclusion_proof(&transactions[1].hash).unwrap();
+        assert!(merkle::verify_proof(&root, &proof));
+        assert!(block.inclusion_proof("unknown").is_none());
+    }
+
+    #[test]
+    fn test_tampered_transaction_fails_hash_check() {
+        let mut tx = sample_transaction();
+        tx.nonce = 1;
+        assert!(!tx.is_hash_valid());
+
+        let mut tx = sample_transaction();
+        tx.chain_id = 1;
+        assert!(!tx.is_hash_valid());
+
+        let mut tx = sample_transaction();
+        tx.max_fee_per_gas = U256::zero();
+        assert!(!tx.is_hash_valid());
+    }
 }
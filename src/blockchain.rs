@@ -1,12 +1,21 @@
-use crate::block::{Block, Transaction};
+use crate::block::{Block, BlockHeader, Receipt, Transaction, TransactionAction, decode_ethereum_transaction};
+use crate::consensus::{precommit_message, proposer_for};
 use crate::errors::NodeError;
+use crate::evm::{self, StateDb, StateEvm};
+use crate::genesis::Genesis;
 use crate::wallet::Wallet;
-use std::collections::{HashMap, HashSet};
-use ed25519_dalek::Signature;
-use chrono::Utc;
-use revm::primitives::{U256 as RevmU256, Bytecode};
+use crate::merkle::{self, hash_leaf, merkle_root, MerkleHash, MerkleProof};
+use crate::storage::ChainStore;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use revm::primitives::{U256 as RevmU256, Bytecode, KECCAK_EMPTY};
+use ethers_core::types::U256;
+use revm::Evm;
+use tracing::warn;
 
-#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Default)]
+/// The maximum total gas of all transactions in a block, measured by their gas limits.
+pub const BLOCK_GAS_LIMIT: u64 = 30_000_000;
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Default, PartialEq)]
 pub struct AccountState {
     pub nonce: u64,
     pub balance: RevmU256,
@@ -14,53 +23,542 @@ pub struct AccountState {
     pub storage: HashMap<RevmU256, RevmU256>,
 }
 
+impl AccountState {
+    /// A Merkle root over the account's storage slots, sorted by slot.
+    pub fn storage_root(&self) -> MerkleHash {
+        let mut slots: Vec<(&RevmU256, &RevmU256)> = self.storage.iter().collect();
+        slots.sort();
+        let leaves: Vec<MerkleHash> = slots.into_iter()
+            .map(|(slot, value)| {
+                let mut data = slot.to_be_bytes::<32>().to_vec();
+                data.extend_from_slice(&value.to_be_bytes::<32>());
+                hash_leaf(&data)
+            })
+            .collect();
+        merkle_root(&leaves)
+    }
+
+    /// The canonical encoding of an account as a state tree leaf: its key, nonce,
+    /// balance, code hash and storage root.
+    pub fn leaf_data(&self, key: &str) -> Vec<u8> {
+        let code_hash = self.bytecode.as_ref().map(Bytecode::hash_slow).unwrap_or(KECCAK_EMPTY);
+        let mut data = key.as_bytes().to_vec();
+        data.extend_from_slice(&self.nonce.to_be_bytes());
+        data.extend_from_slice(&self.balance.to_be_bytes::<32>());
+        data.extend_from_slice(code_hash.as_slice());
+        data.extend_from_slice(&self.storage_root());
+        data
+    }
+}
+
+/// Hashes every account into a state tree leaf, ordered by key.
+fn state_leaves(accounts: &BTreeMap<&str, &AccountState>) -> Vec<MerkleHash> {
+    accounts.iter()
+        .map(|(key, account)| hash_leaf(&account.leaf_data(key)))
+        .collect()
+}
+
+/// Computes the state root: a Merkle root over every account, ordered by key.
+pub fn compute_state_root(accounts: &BTreeMap<&str, &AccountState>) -> String {
+    hex::encode(merkle_root(&state_leaves(accounts)))
+}
+
+/// Formats a 20-byte address as the key used in `Blockchain.state`.
+pub fn account_key(address: &[u8; 20]) -> String {
+    format!("0x{}", hex::encode(address))
+}
+
+/// How many blocks back the chain can be reorganized. Undo data and side
+/// branches older than this are discarded.
+pub const MAX_REORG_DEPTH: u64 = 128;
+
+/// The result of executing a block's transactions: the state diff to apply and one receipt per transaction.
+#[derive(Debug)]
+pub struct BlockExecution {
+    pub changes: HashMap<String, Option<AccountState>>,
+    pub receipts: Vec<Receipt>,
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct Blockchain {
+    /// The chain id from the genesis. Transactions must be signed for it.
+    pub chain_id: u64,
     pub blocks: Vec<Block>,
     pub validator_set: HashSet<String>,
+    /// The account state. It is only modified through `apply_changes`, which keeps `account_leaves` in sync.
     pub state: HashMap<String, AccountState>,
+    /// Receipts of all mined transactions, keyed by transaction hash.
+    #[serde(default)]
+    pub receipts: HashMap<String, Receipt>,
+    /// Finalized blocks that are not on the canonical chain, keyed by header hash.
+    #[serde(default)]
+    side_blocks: HashMap<String, Block>,
+    /// For recent canonical blocks, the previous value of every account the block changed, keyed by height.
+    #[serde(default)]
+    undo_log: HashMap<u64, HashMap<String, Option<AccountState>>>,
+    /// The state tree leaf of every account, ordered by key, so computing a state root
+    /// only rehashes the accounts a block changes.
+    #[serde(skip)]
+    account_leaves: BTreeMap<String, MerkleHash>,
+    /// Durable storage every accepted block is committed to, if the node uses one.
+    #[serde(skip)]
+    storage: Option<Box<dyn ChainStore>>,
+}
+
+/// What happened to a block passed to `Blockchain::import_block`.
+#[derive(Debug)]
+pub enum ImportOutcome {
+    /// The block extended the canonical chain.
+    Extended,
+    /// The block is already on the canonical chain or a known side branch. Nothing changed.
+    Known,
+    /// The block's parent is unknown, or it is below the reorg horizon, so it can't be judged
+    /// against the chain yet. It was not kept, but it may well be valid.
+    Unconnected,
+    /// The block was kept on a side branch that does not outweigh the canonical chain.
+    SideChain,
+    /// The block completed a heavier branch and the chain switched to it.
+    /// `evicted` holds transactions from the abandoned branch that the new one does not include.
+    Reorganized { evicted: Vec<Transaction> },
 }
 
 impl Blockchain {
-    pub fn new(validators: HashSet<String>) -> Self {
-        let genesis_block = Block {
-            header: crate::block::BlockHeader {
-                id: 0,
-                timestamp: Utc::now().timestamp(),
-                previous_hash: "0".repeat(64),
-                validator_pubkey: "system".to_string(),
-                transactions_hash: "0".repeat(64),
-            },
-            transactions: vec![],
-            signature: Signature::from_bytes(&[0; 64]).unwrap(),
-        };
+    /// Starts a chain at the canonical genesis block described by `genesis`.
+    pub fn from_genesis(genesis: &Genesis) -> Self {
+        Self::from_parts(genesis.chain_id, genesis.validator_set(), vec![genesis.block()], genesis.state(), HashMap::new())
+    }
+
+    /// Starts a chain from a verified state snapshot. `block` is the finalized block the
+    /// snapshot was taken at and becomes the first block this node holds; earlier blocks are not needed.
+    pub fn from_snapshot(chain_id: u64, validators: HashSet<String>, block: Block, state: HashMap<String, AccountState>) -> Self {
+        Self::from_parts(chain_id, validators, vec![block], state, HashMap::new())
+    }
 
+    /// Rebuilds a chain loaded from disk.
+    pub fn from_parts(
+        chain_id: u64,
+        validators: HashSet<String>,
+        blocks: Vec<Block>,
+        state: HashMap<String, AccountState>,
+        receipts: HashMap<String, Receipt>,
+    ) -> Self {
+        let account_leaves = state.iter()
+            .map(|(key, account)| (key.clone(), hash_leaf(&account.leaf_data(key))))
+            .collect();
         Self {
-            blocks: vec![genesis_block],
+            chain_id,
+            blocks,
             validator_set: validators,
-            state: HashMap::new(),
+            state,
+            receipts,
+            side_blocks: HashMap::new(),
+            undo_log: HashMap::new(),
+            account_leaves,
+            storage: None,
         }
     }
 
-    /// The core block validation logic. This function is now more focused.
-    /// It validates a block and, if valid, returns the new state. The caller is responsible for committing it.
+    /// Restores the undo data of recent blocks, loaded from storage, so they can still be reorganized away.
+    pub(crate) fn with_undo_log(mut self, undo_log: HashMap<u64, HashMap<String, Option<AccountState>>>) -> Self {
+        self.undo_log = undo_log;
+        self
+    }
+
+    /// The accounts each recent block overwrote, keyed by height.
+    pub(crate) fn undo_log(&self) -> &HashMap<u64, HashMap<String, Option<AccountState>>> {
+        &self.undo_log
+    }
+
+    /// Commits every block accepted from now on to `storage`.
+    pub fn set_storage(&mut self, storage: Box<dyn ChainStore>) {
+        self.storage = Some(storage);
+    }
+
+    /// Replaces the whole chain, e.g. with one restored from a snapshot, rewriting storage to match.
+    pub fn replace_with(&mut self, mut chain: Blockchain) -> Result<(), NodeError> {
+        if let Some(storage) = &self.storage {
+            storage.reset(&chain)?;
+        }
+        chain.storage = self.storage.take();
+        *self = chain;
+        Ok(())
+    }
+
+    /// The height of the latest block.
+    pub fn height(&self) -> u64 {
+        self.blocks.last().map(|block| block.header.id).unwrap_or_default()
+    }
+
+    /// Looks up a canonical block. Chains started from a snapshot hold no blocks below it.
+    pub fn block_by_height(&self, height: u64) -> Option<&Block> {
+        self.blocks.get(self.block_index(height)?)
+    }
+
+    fn block_index(&self, height: u64) -> Option<usize> {
+        let first = self.blocks.first()?.header.id;
+        usize::try_from(height.checked_sub(first)?).ok()
+    }
+
+    /// The header hash of the latest canonical block.
+    pub fn head_hash(&self) -> String {
+        self.blocks.last().map(|head| head.calculate_header_hash()).unwrap_or_default()
+    }
+
+    /// Whether the block is known, either on the canonical chain or on a side branch.
+    pub fn has_block(&self, block: &Block) -> bool {
+        let hash = block.calculate_header_hash();
+        self.side_blocks.contains_key(&hash) || self.is_canonical(block.header.id, &hash)
+    }
+
+    /// Finds a mined transaction by hash, returning it together with its block.
+    pub fn find_transaction(&self, hash: &str) -> Option<(&Block, &Transaction)> {
+        self.blocks.iter().rev().find_map(|block| {
+            block.transactions.iter().find(|tx| tx.hash == hash).map(|tx| (block, tx))
+        })
+    }
+
+    pub fn receipt(&self, tx_hash: &str) -> Option<&Receipt> {
+        self.receipts.get(tx_hash)
+    }
+
+    /// The state root of the current state.
+    pub fn state_root(&self) -> String {
+        self.state_root_after(&HashMap::new())
+    }
+
+    /// Proves that an account is part of the current state root.
+    /// The proof's leaf is `hash_leaf(account.leaf_data(key))`.
+    pub fn account_proof(&self, key: &str) -> Option<MerkleProof> {
+        let index = self.account_leaves.keys().position(|candidate| candidate == key)?;
+        let leaves: Vec<MerkleHash> = self.account_leaves.values().copied().collect();
+        merkle::inclusion_proof(&leaves, index)
+    }
+
+    /// The state root that results from applying `changes` to the current state,
+    /// computed without modifying `self.state`. Only the changed accounts are rehashed.
+    pub fn state_root_after(&self, changes: &HashMap<String, Option<AccountState>>) -> String {
+        let mut leaves: BTreeMap<&str, MerkleHash> = self.account_leaves.iter()
+            .map(|(key, leaf)| (key.as_str(), *leaf))
+            .collect();
+        for (key, change) in changes {
+            match change {
+                Some(account) => { leaves.insert(key.as_str(), hash_leaf(&account.leaf_data(key))); }
+                None => { leaves.remove(key.as_str()); }
+            }
+        }
+        hex::encode(merkle_root(&leaves.into_values().collect::<Vec<_>>()))
+    }
+
+    /// The number of validator signatures needed to finalize a block: more than two thirds of the set.
+    pub fn quorum_size(&self) -> usize {
+        self.validator_set.len() * 2 / 3 + 1
+    }
+
+    /// The core block validation logic. The block must carry a quorum commit and is then
+    /// validated and executed against the current state; its state changes and receipts
+    /// are only applied if the whole block is valid.
     pub fn validate_and_add_block(&mut self, block: Block) -> Result<(), NodeError> {
-        // The validation logic using EVM would be here, as in the previous phase.
-        // For brevity, we are showing the simplified validation logic.
-        self.is_block_valid(&block)?;
+        self.verify_commit(&block)?;
+        let execution = self.validate_proposal(&block)?;
 
-        // Update state based on transactions
-        for tx in &block.transactions {
-            // This part would be replaced by the EVM state transition logic
-            // For example:
-            // let mut evm_db = self.build_evm_db();
-            // process_transaction(&mut evm, tx);
-            // self.state = evm_db.into_state();
+        // Remember what the block overwrote so it can be rolled back in a reorg.
+        let undo: HashMap<String, Option<AccountState>> = execution.changes.keys()
+            .map(|key| (key.clone(), self.state.get(key).cloned()))
+            .collect();
+        let height = block.header.id;
+        if let Some(storage) = &self.storage {
+            storage.commit_block(&block, &execution.changes, &undo, &execution.receipts)?;
         }
+        self.undo_log.insert(height, undo);
+        self.undo_log.retain(|undo_height, _| undo_height + MAX_REORG_DEPTH > height);
+        self.prune_side_blocks(height);
 
+        self.apply_changes(execution.changes);
+        for receipt in execution.receipts {
+            self.receipts.insert(receipt.tx_hash.clone(), receipt);
+        }
         self.blocks.push(block);
         Ok(())
     }
+
+    /// Imports a finalized block received from the network, applying the fork-choice rule.
+    /// Blocks extending the head are added directly. Blocks on another branch are kept, and
+    /// once their branch outweighs the canonical chain the node reorganizes onto it.
+    /// Errors mean the block itself is invalid.
+    pub fn import_block(&mut self, block: Block) -> Result<ImportOutcome, NodeError> {
+        // Peers relay blocks we already have; that is not a fault.
+        if self.has_block(&block) {
+            return Ok(ImportOutcome::Known);
+        }
+        if block.header.previous_hash == self.head_hash() {
+            self.validate_and_add_block(block)?;
+            return Ok(ImportOutcome::Extended);
+        }
+
+        let block_hash = block.calculate_header_hash();
+        let parent_known = self.side_blocks.contains_key(&block.header.previous_hash)
+            || block.header.id.checked_sub(1).is_some_and(|parent| self.is_canonical(parent, &block.header.previous_hash));
+        if block.header.id + MAX_REORG_DEPTH <= self.height() || !parent_known {
+            return Ok(ImportOutcome::Unconnected);
+        }
+
+        // Side blocks can't be executed until the chain switches to their branch, but their commit can be checked now.
+        self.verify_commit(&block)?;
+        self.side_blocks.insert(block_hash.clone(), block);
+
+        // The block is only kept if its branch connects and, when heavier, applies cleanly.
+        let Some((fork_height, branch)) = self.branch_to(&block_hash) else {
+            self.side_blocks.remove(&block_hash);
+            return Err(NodeError::Blockchain("Side branch does not connect to the chain".into()));
+        };
+        if !self.is_heavier(fork_height, &branch) {
+            self.prune_side_blocks(self.height());
+            return Ok(ImportOutcome::SideChain);
+        }
+
+        match self.reorganize(fork_height, branch) {
+            Ok(evicted) => Ok(ImportOutcome::Reorganized { evicted }),
+            Err(e) => {
+                self.side_blocks.remove(&block_hash);
+                Err(e)
+            }
+        }
+    }
+
+    /// Drops side blocks that are too far below `height` to ever be reorganized onto.
+    fn prune_side_blocks(&mut self, height: u64) {
+        self.side_blocks.retain(|_, side| side.header.id + MAX_REORG_DEPTH > height);
+    }
+
+    fn is_canonical(&self, height: u64, hash: &str) -> bool {
+        self.block_by_height(height).is_some_and(|block| block.calculate_header_hash() == hash)
+    }
+
+    /// Walks back from a side block to the canonical chain. Returns the height of the
+    /// fork point and the side branch from just above it up to the given tip.
+    fn branch_to(&self, tip_hash: &str) -> Option<(u64, Vec<Block>)> {
+        let mut branch = Vec::new();
+        let mut cursor = self.side_blocks.get(tip_hash)?;
+        loop {
+            branch.push(cursor.clone());
+            let parent_height = cursor.header.id.checked_sub(1)?;
+            if self.is_canonical(parent_height, &cursor.header.previous_hash) {
+                branch.reverse();
+                return Some((parent_height, branch));
+            }
+            cursor = self.side_blocks.get(&cursor.header.previous_hash)?;
+        }
+    }
+
+    /// The fork-choice rule: the branch with more finalized blocks wins, and between
+    /// branches of equal length the one with more commit signatures. Ties keep the current chain.
+    /// Every block here passed `verify_commit`, so its commit holds no duplicate signers.
+    fn is_heavier(&self, fork_height: u64, branch: &[Block]) -> bool {
+        let weight = |blocks: &[Block]| (blocks.len(), blocks.iter().map(|block| block.commit.len()).sum::<usize>());
+        let canonical = self.block_index(fork_height + 1).map_or(&[][..], |index| &self.blocks[index..]);
+        weight(branch) > weight(canonical)
+    }
+
+    /// Rolls the chain back to `fork_height` and applies `branch` on top of it. If any block of
+    /// the new branch fails, the original chain is restored. Returns the evicted transactions.
+    fn reorganize(&mut self, fork_height: u64, branch: Vec<Block>) -> Result<Vec<Transaction>, NodeError> {
+        let old_branch = self.rollback_to(fork_height)?;
+        warn!(fork_height = %fork_height, old_len = %old_branch.len(), new_len = %branch.len(), "Reorganizing chain.");
+
+        for block in &branch {
+            if let Err(e) = self.validate_and_add_block(block.clone()) {
+                self.rollback_to(fork_height)?;
+                for old in old_branch {
+                    self.validate_and_add_block(old)?;
+                }
+                return Err(NodeError::Blockchain(format!("Reorg aborted, side branch is invalid: {}", e)));
+            }
+        }
+
+        for block in &branch {
+            self.side_blocks.remove(&block.calculate_header_hash());
+        }
+        let included: HashSet<&str> = branch.iter()
+            .flat_map(|block| block.transactions.iter().map(|tx| tx.hash.as_str()))
+            .collect();
+        let evicted = old_branch.iter()
+            .flat_map(|block| block.transactions.iter())
+            .filter(|tx| !included.contains(tx.hash.as_str()))
+            .cloned()
+            .collect();
+        for block in old_branch {
+            self.side_blocks.insert(block.calculate_header_hash(), block);
+        }
+        Ok(evicted)
+    }
+
+    /// Removes the head block and restores the accounts it changed, as recorded by a
+    /// `ChainStore`. Used when replaying a block log; nothing is re-executed.
+    pub fn revert_head(&mut self, restored: HashMap<String, Option<AccountState>>) -> Option<Block> {
+        if self.blocks.len() <= 1 {
+            return None;
+        }
+        let block = self.blocks.pop()?;
+        self.undo_log.remove(&block.header.id);
+        self.apply_changes(restored);
+        for tx in &block.transactions {
+            self.receipts.remove(&tx.hash);
+        }
+        Some(block)
+    }
+
+    /// Removes canonical blocks above `height`, undoing their state changes and receipts.
+    /// Returns the removed blocks in chain order.
+    fn rollback_to(&mut self, height: u64) -> Result<Vec<Block>, NodeError> {
+        if (height + 1..=self.height()).any(|h| !self.undo_log.contains_key(&h)) {
+            return Err(NodeError::Blockchain(format!("Cannot roll back to height {}: undo data unavailable", height)));
+        }
+
+        let mut removed = Vec::new();
+        while self.height() > height {
+            let block = self.blocks.pop().expect("Height is above the fork point");
+            let undo = self.undo_log.remove(&block.header.id).expect("Undo data checked above");
+            if let Some(storage) = &self.storage {
+                if let Err(e) = storage.revert_block(&block, &undo) {
+                    self.undo_log.insert(block.header.id, undo);
+                    self.blocks.push(block);
+                    return Err(e);
+                }
+            }
+            self.apply_changes(undo);
+            for tx in &block.transactions {
+                self.receipts.remove(&tx.hash);
+            }
+            removed.push(block);
+        }
+        removed.reverse();
+        Ok(removed)
+    }
+
+    /// Validates and executes a proposed block without requiring a commit.
+    /// Validators call this before voting for a proposal.
+    pub fn validate_proposal(&self, block: &Block) -> Result<BlockExecution, NodeError> {
+        self.is_block_valid(block)?;
+
+        let execution = self.execute_block(block)?;
+        if block.header.receipts_root != Block::hash_receipts(&execution.receipts) {
+            return Err(NodeError::Blockchain("Receipts root mismatch".into()));
+        }
+        // A mismatch here means this node and the proposer disagree on execution.
+        let state_root = self.state_root_after(&execution.changes);
+        if block.header.state_root != state_root {
+            return Err(NodeError::Blockchain(format!(
+                "State root mismatch: block declares {}, local execution produced {}",
+                block.header.state_root, state_root
+            )));
+        }
+        Ok(execution)
+    }
+
+    /// Checks that the block's commit holds valid precommits from more than two thirds
+    /// of the validator set, each validator signing once, all in the same round.
+    pub fn verify_commit(&self, block: &Block) -> Result<(), NodeError> {
+        let block_hash = block.calculate_header_hash();
+        let round = block.commit.first().map(|commit| commit.round).unwrap_or_default();
+        let message = precommit_message(block.header.id, round, &block_hash);
+        let mut signers = HashSet::new();
+        for commit in &block.commit {
+            if commit.round != round {
+                return Err(NodeError::Blockchain("Commit mixes precommits from different rounds".into()));
+            }
+            if !self.validator_set.contains(&commit.validator_pubkey) {
+                return Err(NodeError::Blockchain(format!("Commit signed by unknown validator {}", commit.validator_pubkey)));
+            }
+            if !Wallet::verify_signature(&commit.validator_pubkey, &message, &commit.signature) {
+                return Err(NodeError::Blockchain(format!("Invalid commit signature from {}", commit.validator_pubkey)));
+            }
+            // A padded commit would otherwise outweigh an honest one in fork choice.
+            if !signers.insert(commit.validator_pubkey.as_str()) {
+                return Err(NodeError::Blockchain(format!("Duplicate commit signature from {}", commit.validator_pubkey)));
+            }
+        }
+
+        if signers.len() < self.quorum_size() {
+            return Err(NodeError::Blockchain(format!(
+                "Block is not finalized: {} of {} required signatures",
+                signers.len(), self.quorum_size()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Runs every transaction in the block through the EVM and returns the resulting state diff and receipts.
+    /// `self.state` is not modified, so a single transaction that cannot be executed discards the whole block.
+    pub fn execute_block(&self, block: &Block) -> Result<BlockExecution, NodeError> {
+        let mut evm = self.evm_for_block(&block.header);
+        let mut receipts = Vec::with_capacity(block.transactions.len());
+
+        for (index, tx) in block.transactions.iter().enumerate() {
+            let result = evm::process_transaction(tx, &mut evm)?;
+            receipts.push(evm::build_receipt(tx, block.header.id, index, result));
+        }
+
+        Ok(BlockExecution { changes: evm.into_context().evm.inner.db.into_changes(), receipts })
+    }
+
+    /// Executes candidate transactions for a new block, skipping any that cannot be executed.
+    /// Used by the proposing validator; returns the included transactions and their execution.
+    pub fn execute_candidates(&self, header: &BlockHeader, candidates: Vec<Transaction>) -> Result<(Vec<Transaction>, BlockExecution), NodeError> {
+        let mut evm = self.evm_for_block(header);
+        let mut included = Vec::with_capacity(candidates.len());
+        let mut receipts = Vec::with_capacity(candidates.len());
+
+        for tx in candidates {
+            if let Err(e) = self.is_transaction_valid(&tx) {
+                warn!("Dropping invalid transaction {}: {}", tx.hash, e);
+                continue;
+            }
+            match evm::process_transaction(&tx, &mut evm) {
+                Ok(result) => {
+                    receipts.push(evm::build_receipt(&tx, header.id, included.len(), result));
+                    included.push(tx);
+                }
+                Err(e) => warn!("Dropping unexecutable transaction {}: {}", tx.hash, e),
+            }
+        }
+
+        Ok((included, BlockExecution { changes: evm.into_context().evm.inner.db.into_changes(), receipts }))
+    }
+
+    /// Creates an EVM over the current state with the block environment of `header`.
+    fn evm_for_block(&self, header: &BlockHeader) -> StateEvm<'_> {
+        Evm::builder()
+            .with_db(StateDb::new(self))
+            // revm rejects transactions for another chain, so it must know which chain this is.
+            .modify_cfg_env(|cfg| cfg.chain_id = self.chain_id)
+            .modify_block_env(|block| {
+                block.number = RevmU256::from(header.id);
+                block.timestamp = RevmU256::from(header.timestamp.max(0) as u64);
+                block.gas_limit = RevmU256::from(BLOCK_GAS_LIMIT);
+                // With no base fee, the full gas fee of every transaction goes to the proposing validator.
+                block.basefee = RevmU256::ZERO;
+                if let Some(coinbase) = Wallet::address_from_public_key(&header.validator_pubkey) {
+                    block.coinbase = coinbase.0.into();
+                }
+            })
+            .build()
+    }
+
+    /// Writes a state diff produced by `execute_block` into `self.state`.
+    fn apply_changes(&mut self, changes: HashMap<String, Option<AccountState>>) {
+        for (key, account) in changes {
+            match account {
+                Some(account) => {
+                    self.account_leaves.insert(key.clone(), hash_leaf(&account.leaf_data(&key)));
+                    self.state.insert(key, account);
+                }
+                None => {
+                    self.account_leaves.remove(&key);
+                    self.state.remove(&key);
+                }
+            }
+        }
+    }
     
     fn is_block_valid(&self, block: &Block) -> Result<(), NodeError> {
         let previous_block = self.blocks.last().ok_or_else(|| NodeError::Blockchain("Genesis block not found".into()))?;
@@ -74,87 +572,307 @@ impl Blockchain {
         if !self.validator_set.contains(&block.header.validator_pubkey) {
             return Err(NodeError::Blockchain("Validator not in the approved set".into()));
         }
-        
-        // ... further validation ...
-        
-        Ok(())
-    }
-}        if !self.validator_set.contains(&block.header.validator_pubkey) {
-            return Err(NodeError::Blockchain("Validator not in the approved set".into()));
+        if proposer_for(&self.validator_set, block.header.id, block.header.round) != Some(&block.header.validator_pubkey) {
+            return Err(NodeError::Blockchain(format!(
+                "Wrong proposer for height {} round {}", block.header.id, block.header.round
+            )));
         }
         let message = block.calculate_header_hash();
         if !Wallet::verify_signature(&block.header.validator_pubkey, message.as_bytes(), &block.signature) {
             return Err(NodeError::Blockchain("Invalid block signature".into()));
         }
+        
+        let block_gas: u64 = block.transactions.iter().map(|tx| tx.gas_limit).fold(0, u64::saturating_add);
+        if block_gas > BLOCK_GAS_LIMIT {
+            return Err(NodeError::Blockchain(format!("Block gas {} exceeds the limit of {}", block_gas, BLOCK_GAS_LIMIT)));
+        }
+        if block.header.transactions_hash != Block::hash_transactions(&block.transactions) {
+            return Err(NodeError::Blockchain("Transactions hash mismatch".into()));
+        }
 
-        // --- Transaction Validation ---
         for tx in &block.transactions {
             self.is_transaction_valid(tx)?;
         }
-
+        
         Ok(())
     }
 
-    /// Validates an individual transaction against the current state.
+    /// Stateless checks on an individual transaction. Nonces and balances are
+    /// enforced by the EVM when the block is executed.
     pub fn is_transaction_valid(&self, tx: &Transaction) -> Result<(), NodeError> {
-        if !Wallet::verify_signature(&tx.sender, tx.hash.as_bytes(), &tx.signature) {
-            return Err(NodeError::Blockchain(format!("Invalid signature on tx {}", tx.hash)));
+        if tx.chain_id != self.chain_id {
+            return Err(NodeError::Blockchain(format!("Wrong chain id on tx {}: {}", tx.hash, tx.chain_id)));
         }
-
-        let sender_state = self.state.get(&tx.sender).ok_or_else(|| NodeError::Blockchain(format!("Sender {} not found", tx.sender)))?;
-
-        if sender_state.balance < tx.amount {
-            return Err(NodeError::Blockchain(format!("Insufficient funds for sender {}", tx.sender)));
+        if !tx.is_hash_valid() {
+            return Err(NodeError::Blockchain(format!("Hash mismatch on tx {}", tx.hash)));
         }
 
-        if sender_state.nonce != tx.nonce {
-            return Err(NodeError::Blockchain(format!("Invalid nonce for sender {}. Expected: {}, got: {}", tx.sender, sender_state.nonce, tx.nonce)));
+        if let TransactionAction::Ethereum { raw } = &tx.action {
+            let (typed, sender) = decode_ethereum_transaction(raw, self.chain_id)?;
+            if sender != tx.sender {
+                return Err(NodeError::Blockchain(format!("Sender {:?} does not match signer on tx {}", tx.sender, tx.hash)));
+            }
+            if typed.nonce() != Some(&U256::from(tx.nonce)) {
+                return Err(NodeError::Blockchain(format!("Nonce does not match signed nonce on tx {}", tx.hash)));
+            }
+            if typed.gas() != Some(&U256::from(tx.gas_limit)) || typed.gas_price() != Some(tx.max_fee_per_gas) {
+                return Err(NodeError::Blockchain(format!("Gas fields do not match signed values on tx {}", tx.hash)));
+            }
+            return Ok(());
         }
 
+        if Wallet::address_from_public_key(&tx.public_key) != Some(tx.sender) {
+            return Err(NodeError::Blockchain(format!("Sender {:?} does not match public key on tx {}", tx.sender, tx.hash)));
+        }
+        if !Wallet::verify_signature(&tx.public_key, tx.hash.as_bytes(), &tx.signature) {
+            return Err(NodeError::Blockchain(format!("Invalid signature on tx {}", tx.hash)));
+        }
         Ok(())
     }
-}        let message = block.calculate_header_hash();
-        if !Wallet::verify_signature(&block.header.validator_pubkey, message.as_bytes(), &block.signature) {
-            println!("Validation Error: Invalid block signature.");
-            return false;
-        }
+}
+
+// --- Unit Tests ---
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::block::{CommitSignature, KOSHER_CHAIN_ID};
+    use crate::genesis::GenesisAccount;
+    use ethers_core::types::Address;
+
+    /// A single-validator chain in which `user` holds funds.
+    pub(crate) fn chain_with(validator: &Wallet, user: &Wallet) -> Blockchain {
+        let mut genesis = Genesis::with_validators([validator.public_key_hex()].into_iter().collect());
+        genesis.alloc.insert(user.address(), GenesisAccount { balance: U256::from(10_000_000), ..Default::default() });
+        Blockchain::from_genesis(&genesis)
+    }
+
+    /// A small transfer from `user`.
+    pub(crate) fn transfer(user: &Wallet, nonce: u64) -> Transaction {
+        let action = TransactionAction::Transfer { recipient: Address::repeat_byte(0x22), amount: U256::from(5) };
+        Transaction::new_signed(user, KOSHER_CHAIN_ID, action, nonce, 21_000, U256::one())
+    }
+
+    /// Builds the next block of a single-validator chain, signed and committed by `validator`.
+    pub(crate) fn next_block(chain: &Blockchain, validator: &Wallet, transactions: Vec<Transaction>) -> Block {
+        let previous_block = chain.blocks.last().unwrap();
+        let mut header = BlockHeader {
+            id: previous_block.header.id + 1,
+            round: 0,
+            timestamp: previous_block.header.timestamp + 1,
+            previous_hash: previous_block.calculate_header_hash(),
+            validator_pubkey: validator.public_key_hex(),
+            transactions_hash: String::new(),
+            receipts_root: String::new(),
+            state_root: String::new(),
+        };
+        let (transactions, execution) = chain.execute_candidates(&header, transactions).unwrap();
+        header.transactions_hash = Block::hash_transactions(&transactions);
+        header.receipts_root = Block::hash_receipts(&execution.receipts);
+        header.state_root = chain.state_root_after(&execution.changes);
+        let mut block = Block { header, transactions, signature: ed25519_dalek::Signature::from_bytes(&[0; 64]), commit: vec![] };
+        resign(&mut block, validator);
+        block
+    }
+
+    fn resign(block: &mut Block, validator: &Wallet) {
+        let hash = block.calculate_header_hash();
+        block.signature = validator.sign(hash.as_bytes());
+        let precommit = validator.sign(&precommit_message(block.header.id, block.header.round, &hash));
+        block.commit = vec![CommitSignature { validator_pubkey: validator.public_key_hex(), round: block.header.round, signature: precommit }];
+    }
+
+    #[test]
+    fn test_rejects_wrong_roots() {
+        let (validator, user) = (Wallet::new(), Wallet::new());
+        let chain = chain_with(&validator, &user);
+        let block = next_block(&chain, &validator, vec![transfer(&user, 0)]);
+        assert!(chain.validate_proposal(&block).is_ok());
+
+        let mut wrong_state = block.clone();
+        wrong_state.header.state_root = hex::encode([7u8; 32]);
+        resign(&mut wrong_state, &validator);
+        let err = chain.validate_proposal(&wrong_state).unwrap_err();
+        assert!(err.to_string().contains("State root mismatch"));
+
+        let mut wrong_receipts = block;
+        wrong_receipts.header.receipts_root = hex::encode([7u8; 32]);
+        resign(&mut wrong_receipts, &validator);
+        let err = chain.validate_proposal(&wrong_receipts).unwrap_err();
+        assert!(err.to_string().contains("Receipts root mismatch"));
+    }
+
+    #[test]
+    fn test_transfer_moves_balance_and_bumps_nonce() {
+        let (validator, user) = (Wallet::new(), Wallet::new());
+        let mut chain = chain_with(&validator, &user);
+        let block = next_block(&chain, &validator, vec![transfer(&user, 0)]);
+        assert_eq!(block.transactions.len(), 1);
+        chain.validate_and_add_block(block).unwrap();
+
+        let receipt = chain.receipts.values().next().unwrap();
+        assert!(receipt.success);
+        let sender = &chain.state[&account_key(user.address().as_fixed_bytes())];
+        assert_eq!(sender.nonce, 1);
+        // The amount plus the gas fee at a price of 1.
+        assert_eq!(sender.balance, RevmU256::from(10_000_000 - 5 - receipt.gas_used));
+        let recipient = &chain.state[&account_key(&[0x22; 20])];
+        assert_eq!(recipient.balance, RevmU256::from(5));
+    }
+
+    #[test]
+    fn test_gas_fee_moves_from_sender_to_proposer() {
+        let (validator, user) = (Wallet::new(), Wallet::new());
+        let mut chain = chain_with(&validator, &user);
+        let action = TransactionAction::Transfer { recipient: Address::repeat_byte(0x22), amount: U256::from(5) };
+        let tx = Transaction::new_signed(&user, KOSHER_CHAIN_ID, action, 0, 21_000, U256::from(3));
+        let block = next_block(&chain, &validator, vec![tx]);
+        chain.validate_and_add_block(block).unwrap();
+
+        let fee = chain.receipts.values().next().unwrap().gas_used * 3;
+        assert_eq!(chain.state[&account_key(user.address().as_fixed_bytes())].balance, RevmU256::from(10_000_000 - 5 - fee));
+        assert_eq!(chain.state[&account_key(validator.address().as_fixed_bytes())].balance, RevmU256::from(fee));
+    }
+
+    #[test]
+    fn test_block_over_gas_limit_is_rejected() {
+        let (validator, user) = (Wallet::new(), Wallet::new());
+        let chain = chain_with(&validator, &user);
+        let action = TransactionAction::Transfer { recipient: Address::repeat_byte(0x22), amount: U256::from(5) };
+        let big = |nonce| Transaction::new_signed(&user, KOSHER_CHAIN_ID, action.clone(), nonce, BLOCK_GAS_LIMIT / 2 + 1, U256::one());
+
+        let mut block = next_block(&chain, &validator, vec![]);
+        block.transactions = vec![big(0), big(1)];
+        block.header.transactions_hash = Block::hash_transactions(&block.transactions);
+        resign(&mut block, &validator);
+        let err = chain.validate_proposal(&block).unwrap_err();
+        assert!(err.to_string().contains("exceeds the limit"));
+    }
+
+    #[test]
+    fn test_commit_requires_quorum_of_distinct_validators() {
+        let validators: Vec<Wallet> = (0..4).map(|_| Wallet::new()).collect();
+        let chain = Blockchain::from_genesis(&Genesis::with_validators(validators.iter().map(Wallet::public_key_hex).collect()));
+        assert_eq!(chain.quorum_size(), 3);
+
+        let mut block = next_block(&chain, &validators[0], vec![]);
+        let hash = block.calculate_header_hash();
+        let sign = |wallet: &Wallet| CommitSignature { validator_pubkey: wallet.public_key_hex(), round: 0, signature: wallet.sign(&precommit_message(1, 0, &hash)) };
+
+        block.commit = validators[..2].iter().map(sign).collect();
+        assert!(chain.verify_commit(&block).is_err());
+
+        block.commit = validators[..3].iter().map(sign).collect();
+        assert!(chain.verify_commit(&block).is_ok());
 
-        // Add more checks here (e.g., timestamp validity, transaction signatures)
+        // An outsider's signature does not count towards the quorum.
+        block.commit = validators[..2].iter().chain([&Wallet::new()]).map(sign).collect();
+        assert!(chain.verify_commit(&block).is_err());
 
-        true
+        // Neither does a second signature from the same validator, and padding a quorum with one is rejected.
+        block.commit = validators[..2].iter().chain([&validators[0]]).map(sign).collect();
+        assert!(chain.verify_commit(&block).is_err());
+        block.commit = validators[..3].iter().chain([&validators[0]]).map(sign).collect();
+        let err = chain.verify_commit(&block).unwrap_err();
+        assert!(err.to_string().contains("Duplicate commit signature"));
+
+        // Signatures over another block are rejected.
+        block.commit = validators[..3].iter().map(sign).collect();
+        block.commit[2].signature = validators[2].sign(b"another block");
+        assert!(chain.verify_commit(&block).is_err());
+
+        // So are precommits for the block from different rounds, or for another height.
+        block.commit = validators[..3].iter().map(sign).collect();
+        block.commit[2].round = 1;
+        block.commit[2].signature = validators[2].sign(&precommit_message(1, 1, &hash));
+        let err = chain.verify_commit(&block).unwrap_err();
+        assert!(err.to_string().contains("different rounds"));
+        block.commit[2] = CommitSignature { round: 0, signature: validators[2].sign(&precommit_message(2, 0, &hash)), ..block.commit[2].clone() };
+        assert!(chain.verify_commit(&block).is_err());
     }
 
-    // --- Persistence functions (save_to_file, load_from_file) remain the same ---
-    // Note: You would adapt load_from_file to also take an initial validator set
-    // or store the set within the chain.json file itself. For simplicity, we'll
-    // re-initialize the validator set on each run in main.rs.
-    pub fn save_to_file(&self, file_path: &str) -> Result<(), Error> {
-        let data = serde_json::to_string_pretty(self).expect("Failed to serialize blockchain");
-        fs::write(file_path, data)
+    #[test]
+    fn test_cached_state_root_matches_full_recomputation() {
+        let (validator, user) = (Wallet::new(), Wallet::new());
+        let mut chain = chain_with(&validator, &user);
+        let block = next_block(&chain, &validator, vec![transfer(&user, 0)]);
+        let expected = block.header.state_root.clone();
+        chain.validate_and_add_block(block).unwrap();
+
+        let accounts: BTreeMap<&str, &AccountState> = chain.state.iter().map(|(key, account)| (key.as_str(), account)).collect();
+        assert_eq!(chain.state_root(), expected);
+        assert_eq!(compute_state_root(&accounts), expected);
+        let key = account_key(user.address().as_fixed_bytes());
+        assert!(chain.account_proof(&key).is_some());
     }
 
-    pub fn load_from_file(file_path: &str, validators: HashSet<String>) -> Self {
-        match fs::read_to_string(file_path) {
-            Ok(data) => {
-                let mut chain: Blockchain = serde_json::from_str(&data).expect("Failed to deserialize");
-                chain.validator_set = validators; // Ensure validator set is up-to-date
-                chain
-            },
-            Err(e) if e.kind() == ErrorKind::NotFound => {
-                println!("No existing blockchain found. Creating a new one.");
-                Self::new(validators)
-            },
-            Err(e) => panic!("Failed to load blockchain file: {}", e),
+    #[test]
+    fn test_import_switches_to_heavier_branch_and_back() {
+        let (validator, user) = (Wallet::new(), Wallet::new());
+        let (mut chain, mut branch_a, mut branch_b) = (chain_with(&validator, &user), chain_with(&validator, &user), chain_with(&validator, &user));
+        let key = |byte: u8| account_key(&[byte; 20]);
+
+        // Branch A pays 0x22 and branch B pays 0x33, both from the user's first nonce.
+        let a1 = next_block(&branch_a, &validator, vec![transfer(&user, 0)]);
+        branch_a.validate_and_add_block(a1.clone()).unwrap();
+        let to_33 = TransactionAction::Transfer { recipient: Address::repeat_byte(0x33), amount: U256::from(5) };
+        let b1_tx = Transaction::new_signed(&user, KOSHER_CHAIN_ID, to_33, 0, 21_000, U256::one());
+        let b1 = next_block(&branch_b, &validator, vec![b1_tx.clone()]);
+        branch_b.validate_and_add_block(b1.clone()).unwrap();
+        let b2 = next_block(&branch_b, &validator, vec![]);
+        branch_b.validate_and_add_block(b2.clone()).unwrap();
+
+        assert!(matches!(chain.import_block(a1.clone()), Ok(ImportOutcome::Extended)));
+        assert!(matches!(chain.import_block(a1.clone()), Ok(ImportOutcome::Known)));
+        // An equally long branch doesn't displace the canonical one.
+        assert!(matches!(chain.import_block(b1.clone()), Ok(ImportOutcome::SideChain)));
+        assert!(matches!(chain.import_block(b1), Ok(ImportOutcome::Known)));
+
+        let evicted = match chain.import_block(b2) {
+            Ok(ImportOutcome::Reorganized { evicted }) => evicted,
+            other => panic!("Expected a reorg, got {:?}", other),
+        };
+        assert_eq!(evicted, a1.transactions);
+        assert_eq!(chain.head_hash(), branch_b.head_hash());
+        assert_eq!(chain.state, branch_b.state);
+        assert!(!chain.state.contains_key(&key(0x22)));
+        assert_eq!(chain.receipts, branch_b.receipts);
+        assert!(!chain.receipts.contains_key(&a1.transactions[0].hash));
+        assert_eq!(chain.undo_log(), branch_b.undo_log());
+        assert_eq!(chain.state_root(), branch_b.state_root());
+
+        // Branch A grows past branch B, and the chain switches back.
+        for _ in 0..2 {
+            let block = next_block(&branch_a, &validator, vec![]);
+            branch_a.validate_and_add_block(block).unwrap();
         }
+        assert!(matches!(chain.import_block(branch_a.blocks[2].clone()), Ok(ImportOutcome::SideChain)));
+        let evicted = match chain.import_block(branch_a.blocks[3].clone()) {
+            Ok(ImportOutcome::Reorganized { evicted }) => evicted,
+            other => panic!("Expected a reorg, got {:?}", other),
+        };
+        assert_eq!(evicted, vec![b1_tx]);
+        assert_eq!(chain.head_hash(), branch_a.head_hash());
+        assert_eq!(chain.state, branch_a.state);
+        assert!(!chain.state.contains_key(&key(0x33)));
+        assert_eq!(chain.receipts, branch_a.receipts);
+        assert_eq!(chain.undo_log(), branch_a.undo_log());
+        assert_eq!(chain.state_root(), branch_a.state_root());
     }
-}    pub fn load_from_file(file_path: &str) -> Self {
-        match fs::read_to_string(file_path) {
-            Ok(data) => serde_json::from_str(&data).expect("Failed to deserialize blockchain data"),
-            Err(e) if e.kind() == ErrorKind::NotFound => {
-                println!("No existing blockchain found. Creating a new one.");
-                Self::new()
-            },
-            Err(e) => panic!("Failed to load blockchain file: {}", e),
+
+    #[test]
+    fn test_import_of_unconnected_block_is_not_an_error() {
+        let (validator, user) = (Wallet::new(), Wallet::new());
+        let mut chain = chain_with(&validator, &user);
+        let mut ahead = chain_with(&validator, &user);
+        for _ in 0..2 {
+            let block = next_block(&ahead, &validator, vec![]);
+            ahead.validate_and_add_block(block).unwrap();
         }
+        assert!(matches!(chain.import_block(ahead.blocks[2].clone()), Ok(ImportOutcome::Unconnected)));
+        assert_eq!(chain.height(), 0);
+
+        let mut padded = next_block(&chain, &validator, vec![]);
+        padded.commit.push(padded.commit[0].clone());
+        assert!(chain.import_block(padded).is_err());
     }
 }
@@ -0,0 +1,159 @@
+use crate::block::KOSHER_CHAIN_ID;
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+/// Command-line options for the Kosher Chain node.
+#[derive(Parser)]
+#[command(name = "kosher_chain", version, about = "Kosher Chain node")]
+pub struct Cli {
+    /// Path to the node's config file.
+    #[arg(long, global = true, default_value = "config.toml")]
+    pub config: PathBuf,
+
+    /// Directory for chain data. Overrides `[storage] data_dir` in the config file.
+    #[arg(long, global = true)]
+    pub data_dir: Option<PathBuf>,
+
+    /// What to do. Without a command the node is started, as with `node run`.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the node.
+    #[command(subcommand)]
+    Node(NodeCommand),
+    /// Manage ed25519 keys.
+    #[command(subcommand)]
+    Keys(KeysCommand),
+    /// Set up a new chain.
+    #[command(subcommand)]
+    Genesis(GenesisCommand),
+    /// Sign and submit transactions.
+    #[command(subcommand)]
+    Tx(TxCommand),
+    /// Export and import the chain data.
+    #[command(subcommand)]
+    Chain(ChainCommand),
+    /// Read the chain state.
+    #[command(subcommand)]
+    State(StateCommand),
+    /// Check the validator set.
+    #[command(subcommand)]
+    Validators(ValidatorsCommand),
+}
+
+#[derive(Subcommand)]
+pub enum NodeCommand {
+    /// Start all node services.
+    Run,
+}
+
+#[derive(Subcommand)]
+pub enum KeysCommand {
+    /// Generate a new key file. Fails if the file already exists.
+    Generate {
+        #[arg(long)]
+        key_file: PathBuf,
+    },
+    /// Print the public key and account address of a key file.
+    Show {
+        #[arg(long)]
+        key_file: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum GenesisCommand {
+    /// Write the genesis file (or the validators file, if no genesis file is configured)
+    /// and initialize the data directory with the genesis block.
+    Init {
+        /// Hex-encoded ed25519 public key of an initial validator. Repeat for each validator.
+        #[arg(long = "validator", required = true)]
+        validators: Vec<String>,
+        /// Initial balance as ADDRESS=AMOUNT, in decimal. Repeat for each account.
+        #[arg(long)]
+        alloc: Vec<String>,
+        /// Pre-deployed contract as ADDRESS=FILE, where FILE holds hex-encoded runtime bytecode.
+        #[arg(long = "contract")]
+        contracts: Vec<String>,
+        /// The network's chain id. Only written to a genesis file.
+        #[arg(long, default_value_t = KOSHER_CHAIN_ID)]
+        chain_id: u64,
+        /// Overwrite an existing file.
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TxCommand {
+    /// Sign a transaction and print it as JSON.
+    Sign(SignArgs),
+    /// Submit a signed transaction, as printed by `tx sign`, to a node's API.
+    Send {
+        /// File containing the signed transaction JSON.
+        #[arg(long)]
+        file: PathBuf,
+        /// Base URL of the node's API.
+        #[arg(long, default_value = "http://127.0.0.1:3000")]
+        api: String,
+    },
+}
+
+#[derive(Args)]
+pub struct SignArgs {
+    /// Key file of the sender.
+    #[arg(long)]
+    pub key_file: PathBuf,
+    /// Recipient address, or the contract to call when `--data` is given.
+    /// Leave out together with `--data` to deploy a contract.
+    #[arg(long)]
+    pub to: Option<String>,
+    /// Amount of the native token to send, in decimal.
+    #[arg(long, default_value = "0")]
+    pub value: String,
+    /// Hex-encoded call data or contract bytecode.
+    #[arg(long)]
+    pub data: Option<String>,
+    #[arg(long)]
+    pub nonce: u64,
+    #[arg(long, default_value_t = 21_000)]
+    pub gas_limit: u64,
+    /// Maximum fee per gas, in decimal.
+    #[arg(long, default_value = "0")]
+    pub max_fee_per_gas: String,
+    /// The chain id of the network the transaction is for, as set in its genesis file.
+    #[arg(long, default_value_t = KOSHER_CHAIN_ID)]
+    pub chain_id: u64,
+}
+
+#[derive(Subcommand)]
+pub enum ChainCommand {
+    /// Write every block, the account state and receipts to a JSON file.
+    Export {
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Replace the local chain with an exported one, after re-executing and verifying every block.
+    Import {
+        #[arg(long)]
+        file: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum StateCommand {
+    /// Print an account's nonce, balance, code and storage.
+    Inspect {
+        /// The account address, hex-encoded.
+        address: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ValidatorsCommand {
+    /// Check the validators file, the local validator key and the latest block's commit.
+    Verify,
+}
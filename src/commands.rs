@@ -0,0 +1,347 @@
+use crate::block::{Transaction, TransactionAction};
+use crate::blockchain::{account_key, Blockchain};
+use crate::cli::{ChainCommand, Command, GenesisCommand, KeysCommand, NodeCommand, SignArgs, StateCommand, TxCommand, ValidatorsCommand};
+use crate::config::{Config, StorageBackend};
+use crate::errors::NodeError;
+use crate::genesis::Genesis;
+use crate::wallet::Wallet;
+use crate::{persistence, storage};
+use ed25519_dalek::VerifyingKey;
+use ethers_core::types::{Address, U256};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Loads the config file and applies the `--data-dir` override.
+pub fn load_config(config_path: &Path, data_dir: Option<PathBuf>) -> Result<Config, NodeError> {
+    let mut config = Config::load(config_path)?;
+    if let Some(data_dir) = data_dir {
+        config.storage.data_dir = data_dir;
+    }
+    Ok(config)
+}
+
+/// Opens the chain in the configured data directory with the configured storage backend,
+/// creating a genesis block if the directory is empty.
+pub fn open_chain(config: &Config) -> Result<Blockchain, NodeError> {
+    let genesis = config.chain.load_genesis()?;
+    fs::create_dir_all(&config.storage.data_dir)?;
+    match config.storage.backend {
+        StorageBackend::Sled => storage::open_chain(&config.storage.data_dir, &genesis),
+        StorageBackend::Json => persistence::open_chain(&genesis, &config.storage.data_dir),
+    }
+}
+
+/// Runs a CLI command. Commands that don't touch the chain don't need a config file.
+pub async fn run(command: Command, config_path: &Path, data_dir: Option<PathBuf>) -> Result<(), NodeError> {
+    match command {
+        Command::Node(NodeCommand::Run) => crate::run_node(load_config(config_path, data_dir)?).await,
+        Command::Keys(command) => keys(command),
+        Command::Tx(TxCommand::Sign(args)) => sign_transaction(args),
+        Command::Tx(TxCommand::Send { file, api }) => send_transaction(&file, &api).await,
+        Command::Genesis(command) => genesis(command, &load_config(config_path, data_dir)?),
+        Command::Chain(command) => chain(command, &load_config(config_path, data_dir)?),
+        Command::State(StateCommand::Inspect { address }) => inspect_state(&address, &load_config(config_path, data_dir)?),
+        Command::Validators(ValidatorsCommand::Verify) => verify_validators(&load_config(config_path, data_dir)?),
+    }
+}
+
+fn keys(command: KeysCommand) -> Result<(), NodeError> {
+    let key_file = match &command {
+        KeysCommand::Generate { key_file } => {
+            if key_file.exists() {
+                return Err(NodeError::Config(format!("Key file {} already exists", key_file.display())));
+            }
+            key_file
+        }
+        KeysCommand::Show { key_file } => {
+            if !key_file.exists() {
+                return Err(NodeError::Config(format!("Key file {} not found", key_file.display())));
+            }
+            key_file
+        }
+    };
+
+    let wallet = Wallet::load_or_create(key_file)?;
+    println!("Public key: {}", wallet.public_key_hex());
+    println!("Address:    {:?}", wallet.address());
+    Ok(())
+}
+
+fn genesis(command: GenesisCommand, config: &Config) -> Result<(), NodeError> {
+    let GenesisCommand::Init { validators, alloc, contracts, chain_id, force } = command;
+    for pubkey in &validators {
+        parse_public_key(pubkey)?;
+    }
+
+    let (path, data) = match (&config.chain.genesis_file, &config.chain.validators_file) {
+        (Some(path), _) => {
+            let mut genesis = Genesis::with_validators(HashSet::new());
+            genesis.chain_id = chain_id;
+            genesis.validators = validators.clone();
+            genesis.timestamp = chrono::Utc::now().timestamp();
+            for entry in &alloc {
+                let (address, balance) = split_assignment(entry)?;
+                genesis.alloc.entry(parse_address(address)?).or_default().balance = parse_amount(balance)?;
+            }
+            for entry in &contracts {
+                let (address, code_file) = split_assignment(entry)?;
+                let code = hex::decode(fs::read_to_string(code_file)?.trim().trim_start_matches("0x"))
+                    .map_err(|e| NodeError::Config(format!("Invalid bytecode in {}: {}", code_file, e)))?;
+                genesis.alloc.entry(parse_address(address)?).or_default().code = Some(code.into());
+            }
+            (path, serde_json::to_value(&genesis)?)
+        }
+        (None, Some(path)) => {
+            if !alloc.is_empty() || !contracts.is_empty() {
+                return Err(NodeError::Config("Initial accounts need a `genesis_file` under [chain]".into()));
+            }
+            (path, serde_json::json!({ "validators": validators }))
+        }
+        (None, None) => return Err(NodeError::Config("Set `genesis_file` or `validators_file` under [chain]".into())),
+    };
+
+    // The genesis block is only created for an empty data directory. Refuse before writing,
+    // so an existing chain is never left next to a genesis file it was not started from.
+    let data_dir = &config.storage.data_dir;
+    if storage::database_exists(data_dir) || persistence::state_exists(data_dir) {
+        return Err(NodeError::Config(format!(
+            "{} already holds a chain. Initialize a new genesis in an empty data directory.", data_dir.display()
+        )));
+    }
+    let path = Path::new(path);
+    if path.exists() && !force {
+        return Err(NodeError::Config(format!("{} already exists. Pass --force to overwrite it.", path.display())));
+    }
+    fs::write(path, serde_json::to_string_pretty(&data)?)?;
+    println!("Wrote genesis with {} validators to {}", validators.len(), path.display());
+
+    let chain = open_chain(config)?;
+    println!("Genesis block: {}", chain.block_by_height(0).map(|block| block.calculate_header_hash()).unwrap_or_default());
+    Ok(())
+}
+
+fn sign_transaction(args: SignArgs) -> Result<(), NodeError> {
+    if !args.key_file.exists() {
+        return Err(NodeError::Config(format!("Key file {} not found", args.key_file.display())));
+    }
+    let wallet = Wallet::load_or_create(&args.key_file)?;
+    let to = args.to.as_deref().map(parse_address).transpose()?;
+    let value = parse_amount(&args.value)?;
+    let action = match (to, args.data) {
+        (Some(recipient), None) => TransactionAction::Transfer { recipient, amount: value },
+        (to, Some(data)) => TransactionAction::Call {
+            to,
+            data: hex::decode(data.trim_start_matches("0x"))
+                .map_err(|e| NodeError::Config(format!("Invalid --data: {}", e)))?,
+            value,
+        },
+        (None, None) => return Err(NodeError::Config("Pass --to, --data or both".into())),
+    };
+
+    let tx = Transaction::new_signed(&wallet, args.chain_id, action, args.nonce, args.gas_limit, parse_amount(&args.max_fee_per_gas)?);
+    println!("{}", serde_json::to_string_pretty(&tx)?);
+    Ok(())
+}
+
+async fn send_transaction(file: &Path, api: &str) -> Result<(), NodeError> {
+    let tx: Transaction = serde_json::from_str(&fs::read_to_string(file)?)?;
+    let url = format!("{}/transaction", api.trim_end_matches('/'));
+    let response = reqwest::Client::new().post(&url).json(&tx).send().await
+        .map_err(|e| NodeError::Api(format!("Failed to reach {}: {}", url, e)))?;
+
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    if !status.is_success() {
+        return Err(NodeError::Api(format!("Node rejected transaction {}: {} {}", tx.hash, status, body)));
+    }
+    println!("Submitted transaction {}: {}", tx.hash, body);
+    Ok(())
+}
+
+fn chain(command: ChainCommand, config: &Config) -> Result<(), NodeError> {
+    match command {
+        ChainCommand::Export { out } => {
+            let chain = open_chain(config)?;
+            persistence::export_chain(&chain, &out)?;
+            println!("Exported {} blocks to {}", chain.blocks.len(), out.display());
+        }
+        ChainCommand::Import { file } => {
+            let imported = persistence::import_chain(&file, &config.chain.load_genesis()?)?;
+            let height = imported.height();
+            open_chain(config)?.replace_with(imported)?;
+            println!("Imported and verified {} blocks. Current block height: {}", height + 1, height);
+        }
+    }
+    Ok(())
+}
+
+fn inspect_state(address: &str, config: &Config) -> Result<(), NodeError> {
+    let address = parse_address(address)?;
+    let chain = open_chain(config)?;
+    let Some(account) = chain.state.get(&account_key(&address.0)) else {
+        println!("Account {:?} does not exist at block height {}", address, chain.height());
+        return Ok(());
+    };
+
+    println!("Account {:?} at block height {}", address, chain.height());
+    println!("  Nonce:   {}", account.nonce);
+    println!("  Balance: {}", account.balance);
+    match &account.bytecode {
+        Some(code) => println!("  Code:    {} bytes, hash {:?}", code.len(), code.hash_slow()),
+        None => println!("  Code:    none"),
+    }
+    let mut slots: Vec<_> = account.storage.iter().collect();
+    slots.sort();
+    println!("  Storage: {} slots", slots.len());
+    for (slot, value) in slots {
+        println!("    {:#x} = {:#x}", slot, value);
+    }
+    Ok(())
+}
+
+fn verify_validators(config: &Config) -> Result<(), NodeError> {
+    let validators = config.chain.load_validators()?;
+    let mut problems = Vec::new();
+    let mut seen = HashSet::new();
+    for pubkey in &validators {
+        if let Err(e) = parse_public_key(pubkey) {
+            problems.push(e.to_string());
+        }
+        if !seen.insert(pubkey) {
+            problems.push(format!("Validator {} is listed more than once", pubkey));
+        }
+    }
+
+    let chain = open_chain(config)?;
+    println!("{} validators, {} signatures needed to finalize a block", seen.len(), chain.quorum_size());
+
+    if let Some(validator) = &config.validator {
+        if Path::new(&validator.key_file).exists() {
+            let pubkey = Wallet::load_or_create(Path::new(&validator.key_file))?.public_key_hex();
+            if seen.contains(&pubkey) {
+                println!("Local validator key {} is in the set", pubkey);
+            } else {
+                problems.push(format!("Local validator key {} is not in the set", pubkey));
+            }
+        }
+    }
+
+    let head = chain.blocks.last().expect("Chain always contains a block");
+    if head.header.id > 0 {
+        match chain.verify_commit(head) {
+            Ok(()) => println!("Commit of block {} verifies against the set", head.header.id),
+            Err(e) => problems.push(format!("Commit of block {} does not verify: {}", head.header.id, e)),
+        }
+    }
+
+    if problems.is_empty() {
+        println!("Validator set OK");
+        return Ok(());
+    }
+    for problem in &problems {
+        eprintln!("{}", problem);
+    }
+    Err(NodeError::Config(format!("Validator set has {} problems", problems.len())))
+}
+
+fn split_assignment(entry: &str) -> Result<(&str, &str), NodeError> {
+    entry.split_once('=').ok_or_else(|| NodeError::Config(format!("Expected ADDRESS=VALUE, got {}", entry)))
+}
+
+fn parse_public_key(pubkey: &str) -> Result<(), NodeError> {
+    hex::decode(pubkey).ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .filter(|bytes| VerifyingKey::from_bytes(bytes).is_ok())
+        .map(|_| ())
+        .ok_or_else(|| NodeError::Config(format!("{} is not a valid ed25519 public key", pubkey)))
+}
+
+fn parse_address(address: &str) -> Result<Address, NodeError> {
+    address.parse().map_err(|_| NodeError::Config(format!("{} is not a valid address", address)))
+}
+
+fn parse_amount(amount: &str) -> Result<U256, NodeError> {
+    U256::from_dec_str(amount).map_err(|_| NodeError::Config(format!("{} is not a valid amount", amount)))
+}
+
+// --- Unit Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::KOSHER_CHAIN_ID;
+    use crate::blockchain::tests::{next_block, transfer};
+    use crate::config::{ApiConfig, ChainConfig, P2pConfig, StorageConfig, WitnessConfig};
+    use crate::genesis::GenesisAccount;
+
+    fn config(genesis_file: &Path, data_dir: PathBuf) -> Config {
+        Config {
+            api: ApiConfig { listen_address: "127.0.0.1:0".into() },
+            p2p: P2pConfig { listen_address: "/ip4/127.0.0.1/tcp/0".into() },
+            witness: WitnessConfig { xrpl_node_url: String::new(), door_account: String::new(), confirmation_threshold: 1 },
+            chain: ChainConfig { genesis_file: Some(genesis_file.to_string_lossy().into_owned()), validators_file: None },
+            validator: None,
+            storage: StorageConfig { backend: StorageBackend::Json, data_dir },
+            mempool: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_argument_parsing() {
+        assert_eq!(split_assignment("0xab=10").unwrap(), ("0xab", "10"));
+        assert!(split_assignment("0xab").is_err());
+
+        assert_eq!(parse_amount("1000000000000000000000").unwrap(), U256::exp10(21));
+        assert!(parse_amount("-1").is_err());
+        assert!(parse_amount("1e18").is_err());
+
+        assert!(parse_public_key(&Wallet::new().public_key_hex()).is_ok());
+        assert!(parse_public_key("abcd").is_err());
+        assert!(parse_public_key(&"zz".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let (validator, user) = (Wallet::new(), Wallet::new());
+        let mut genesis = Genesis::with_validators([validator.public_key_hex()].into_iter().collect());
+        genesis.alloc.insert(user.address(), GenesisAccount { balance: U256::from(10_000_000), ..Default::default() });
+        let genesis_file = dir.path().join("genesis.json");
+        fs::write(&genesis_file, serde_json::to_string(&genesis).unwrap()).unwrap();
+
+        let source = config(&genesis_file, dir.path().join("source"));
+        let mut chain = open_chain(&source).unwrap();
+        for nonce in 0..2 {
+            let block = next_block(&chain, &validator, vec![transfer(&user, nonce)]);
+            chain.validate_and_add_block(block).unwrap();
+        }
+
+        let out = dir.path().join("export.json");
+        super::chain(ChainCommand::Export { out: out.clone() }, &source).unwrap();
+        let target = config(&genesis_file, dir.path().join("target"));
+        super::chain(ChainCommand::Import { file: out }, &target).unwrap();
+
+        let imported = open_chain(&target).unwrap();
+        assert_eq!(imported.height(), 2);
+        assert_eq!(imported.head_hash(), chain.head_hash());
+        assert_eq!(imported.state_root(), chain.state_root());
+    }
+
+    #[test]
+    fn test_genesis_refuses_existing_chain() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let validator = Wallet::new().public_key_hex();
+        let genesis_file = dir.path().join("genesis.json");
+        let mut config = config(&genesis_file, dir.path().join("data"));
+        // The database is created along with the genesis block.
+        config.storage.backend = StorageBackend::Sled;
+        let init = |force| GenesisCommand::Init {
+            validators: vec![validator.clone()], alloc: vec![], contracts: vec![], chain_id: KOSHER_CHAIN_ID, force,
+        };
+
+        genesis(init(false), &config).unwrap();
+        let written = fs::read_to_string(&genesis_file).unwrap();
+        assert!(genesis(init(true), &config).is_err());
+        assert_eq!(fs::read_to_string(&genesis_file).unwrap(), written);
+    }
+}
@@ -1,6 +1,9 @@
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs;
+use std::path::{Path, PathBuf};
 use crate::errors::NodeError;
+use crate::genesis::Genesis;
 
 #[derive(Deserialize)]
 pub struct Config {
@@ -9,6 +12,10 @@ pub struct Config {
     pub witness: WitnessConfig,
     pub chain: ChainConfig,
     pub validator: Option<ValidatorConfig>, // Validator config is optional
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub mempool: MempoolConfig,
 }
 
 #[derive(Deserialize)]
@@ -30,7 +37,72 @@ pub struct WitnessConfig {
 
 #[derive(Deserialize)]
 pub struct ChainConfig {
-    pub validators_file: String,
+    /// The `genesis.json` describing the chain. Takes precedence over `validators_file`.
+    #[serde(default)]
+    pub genesis_file: Option<String>,
+    /// A bare list of validators, for chains without a genesis file.
+    #[serde(default)]
+    pub validators_file: Option<String>,
+}
+
+impl ChainConfig {
+    /// The genesis of the configured chain. Without a genesis file, it is an empty
+    /// genesis with the validators from `validators_file`.
+    pub fn load_genesis(&self) -> Result<Genesis, NodeError> {
+        match &self.genesis_file {
+            Some(path) => Genesis::load(Path::new(path)),
+            None => Ok(Genesis::with_validators(self.load_validators()?.into_iter().collect())),
+        }
+    }
+
+    /// Reads the initial validator public keys, in file order.
+    pub fn load_validators(&self) -> Result<Vec<String>, NodeError> {
+        if let Some(path) = &self.genesis_file {
+            return Ok(Genesis::load(Path::new(path))?.validators);
+        }
+        let Some(path) = &self.validators_file else {
+            return Err(NodeError::Config("Set `genesis_file` or `validators_file` under [chain]".into()));
+        };
+        let content = fs::read_to_string(path)
+            .map_err(|e| NodeError::Config(format!("Failed to read validators file {}: {}", path, e)))?;
+        let mut data: HashMap<String, Vec<String>> = serde_json::from_str(&content)?;
+        data.remove("validators")
+            .ok_or_else(|| NodeError::Config("`validators` key not found in validators file".into()))
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// An embedded database that commits every block as it is accepted.
+    Sled,
+    /// The legacy JSON state file, written on shutdown.
+    Json,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    /// The directory holding this node's chain data. Nodes for different chains need different directories.
+    pub data_dir: PathBuf,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: StorageBackend::Sled,
+            data_dir: PathBuf::from("."),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct MempoolConfig {
+    /// Keep a journal of transactions submitted to this node's API in the data directory,
+    /// so they are not lost when the node restarts.
+    #[serde(default)]
+    pub journal: bool,
 }
 
 #[derive(Deserialize)]
@@ -39,11 +111,26 @@ pub struct ValidatorConfig {
 }
 
 impl Config {
-    pub fn load(path: &str) -> Result<Self, NodeError> {
+    /// Loads the config file. Relative paths inside it are resolved against the
+    /// directory the file is in, so a node can be started from any working directory.
+    pub fn load(path: &Path) -> Result<Self, NodeError> {
         let content = fs::read_to_string(path)
-            .map_err(|e| NodeError::Config(format!("Failed to read config file {}: {}", path, e)))?;
+            .map_err(|e| NodeError::Config(format!("Failed to read config file {}: {}", path.display(), e)))?;
             
-        toml::from_str(&content)
-            .map_err(|e| NodeError::Config(format!("Failed to parse config file: {}", e)))
+        let mut config: Self = toml::from_str(&content)
+            .map_err(|e| NodeError::Config(format!("Failed to parse config file: {}", e)))?;
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        config.chain.genesis_file = config.chain.genesis_file.map(|path| resolve(base, &path));
+        config.chain.validators_file = config.chain.validators_file.map(|path| resolve(base, &path));
+        config.storage.data_dir = base.join(&config.storage.data_dir);
+        if let Some(validator) = config.validator.as_mut() {
+            validator.key_file = resolve(base, &validator.key_file);
+        }
+        Ok(config)
     }
 }
+
+fn resolve(base: &Path, path: &str) -> String {
+    base.join(path).to_string_lossy().into_owned()
+}
@@ -0,0 +1,207 @@
+use crate::block::{Block, CommitSignature};
+use crate::wallet::Wallet;
+use ed25519_dalek::Signature;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Returns the validator allowed to propose the block at `height` in `round`.
+/// Validators take turns in order of their sorted public keys; every timed-out
+/// round moves the turn on to the next validator.
+pub fn proposer_for(validator_set: &HashSet<String>, height: u64, round: u32) -> Option<&String> {
+    let mut validators: Vec<&String> = validator_set.iter().collect();
+    if validators.is_empty() {
+        return None;
+    }
+    validators.sort();
+    let index = (height.wrapping_add(u64::from(round)) % validators.len() as u64) as usize;
+    Some(validators[index])
+}
+
+/// The two voting steps of a round. A validator prevotes for a valid proposal; once a
+/// quorum has prevoted for the same block in a round (a polka) it locks on that block
+/// and precommits it. Precommits from a quorum in one round form the block's commit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VoteKind {
+    Prevote,
+    Precommit,
+}
+
+/// The bytes signed by a precommit. A block's commit is made of these signatures.
+pub fn precommit_message(height: u64, round: u32, block_hash: &str) -> Vec<u8> {
+    format!("precommit:{}:{}:{}", height, round, block_hash).into_bytes()
+}
+
+fn prevote_message(height: u64, round: u32, block_hash: &str) -> Vec<u8> {
+    format!("prevote:{}:{}:{}", height, round, block_hash).into_bytes()
+}
+
+/// A validator's vote for a proposed block, gossiped on the consensus topic.
+/// Every field is covered by the signature.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Vote {
+    pub kind: VoteKind,
+    pub height: u64,
+    pub round: u32,
+    pub block_hash: String,
+    pub validator_pubkey: String,
+    pub signature: Signature,
+}
+
+impl Vote {
+    /// Signs a vote of `kind` for the block with `block_hash` at `height` in `round`.
+    pub fn sign(wallet: &Wallet, kind: VoteKind, height: u64, round: u32, block_hash: String) -> Self {
+        let mut vote = Self {
+            kind,
+            height,
+            round,
+            block_hash,
+            validator_pubkey: wallet.public_key_hex(),
+            signature: Signature::from_bytes(&[0; 64]),
+        };
+        vote.signature = wallet.sign(&vote.message());
+        vote
+    }
+
+    fn message(&self) -> Vec<u8> {
+        match self.kind {
+            VoteKind::Prevote => prevote_message(self.height, self.round, &self.block_hash),
+            VoteKind::Precommit => precommit_message(self.height, self.round, &self.block_hash),
+        }
+    }
+
+    fn is_signed_by(&self, validator_set: &HashSet<String>) -> bool {
+        validator_set.contains(&self.validator_pubkey)
+            && Wallet::verify_signature(&self.validator_pubkey, &self.message(), &self.signature)
+    }
+
+    fn into_commit(self) -> CommitSignature {
+        CommitSignature { validator_pubkey: self.validator_pubkey, round: self.round, signature: self.signature }
+    }
+}
+
+/// What a vote added to `VoteCollector` decided.
+#[derive(Debug)]
+pub enum VoteOutcome {
+    /// Nothing new was decided.
+    Pending,
+    /// A quorum prevoted for `block_hash` in `round`. Validators lock on it and precommit it.
+    Polka { height: u64, round: u32, block_hash: String },
+    /// A quorum precommitted this block in one round. The block carries their commit.
+    Finalized(Box<Block>),
+}
+
+/// Collects proposals and votes until a block gathers a quorum of signatures.
+/// Votes can arrive before the proposal they refer to, so both are keyed by block hash.
+#[derive(Default)]
+pub struct VoteCollector {
+    proposals: HashMap<String, Block>,
+    /// The signers of prevotes by height and round, then block hash.
+    prevotes: HashMap<(u64, u32), HashMap<String, HashSet<String>>>,
+    precommits: HashMap<String, Vec<Vote>>,
+}
+
+impl VoteCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a proposal that has passed validation. Returns the finalized block
+    /// if enough precommits for it have already been collected.
+    pub fn add_proposal(&mut self, block: Block, validator_set: &HashSet<String>, quorum: usize) -> Option<Block> {
+        let block_hash = block.calculate_header_hash();
+        self.proposals.insert(block_hash.clone(), block);
+        self.try_finalize(&block_hash, validator_set, quorum)
+    }
+
+    /// Records a vote after checking its signature, and reports a polka or a finalized block
+    /// once the vote completes a quorum. A validator counts once per height, round and step.
+    pub fn add_vote(&mut self, vote: Vote, validator_set: &HashSet<String>, quorum: usize) -> VoteOutcome {
+        if !vote.is_signed_by(validator_set) {
+            return VoteOutcome::Pending;
+        }
+
+        match vote.kind {
+            VoteKind::Prevote => {
+                let round = self.prevotes.entry((vote.height, vote.round)).or_default();
+                if round.values().any(|signers| signers.contains(&vote.validator_pubkey)) {
+                    return VoteOutcome::Pending;
+                }
+                let signers = round.entry(vote.block_hash.clone()).or_default();
+                signers.insert(vote.validator_pubkey);
+                if signers.len() < quorum {
+                    return VoteOutcome::Pending;
+                }
+                VoteOutcome::Polka { height: vote.height, round: vote.round, block_hash: vote.block_hash }
+            }
+            VoteKind::Precommit => {
+                let block_hash = vote.block_hash.clone();
+                let votes = self.precommits.entry(block_hash.clone()).or_default();
+                if votes.iter().all(|existing| (&existing.validator_pubkey, existing.round) != (&vote.validator_pubkey, vote.round)) {
+                    votes.push(vote);
+                }
+                self.try_finalize(&block_hash, validator_set, quorum).map_or(VoteOutcome::Pending, |block| VoteOutcome::Finalized(Box::new(block)))
+            }
+        }
+    }
+
+    /// Finalizes the proposal once a quorum precommitted it in the same round. Precommits
+    /// for another height than the proposal's were signed for a different block and don't count.
+    fn try_finalize(&mut self, block_hash: &str, validator_set: &HashSet<String>, quorum: usize) -> Option<Block> {
+        let height = self.proposals.get(block_hash)?.header.id;
+        let votes = self.precommits.get(block_hash)?;
+        let mut by_round: HashMap<u32, Vec<&Vote>> = HashMap::new();
+        for vote in votes.iter().filter(|vote| vote.height == height && validator_set.contains(&vote.validator_pubkey)) {
+            by_round.entry(vote.round).or_default().push(vote);
+        }
+        let round = by_round.into_iter().find(|(_, votes)| votes.len() >= quorum)?.0;
+
+        let mut block = self.proposals.remove(block_hash)?;
+        block.commit = self.precommits.remove(block_hash)?.into_iter()
+            .filter(|vote| vote.height == height && vote.round == round)
+            .map(Vote::into_commit)
+            .collect();
+        Some(block)
+    }
+
+    /// Drops proposals and votes for heights that are already part of the chain.
+    pub fn prune(&mut self, finalized_height: u64) {
+        self.proposals.retain(|_, block| block.header.id > finalized_height);
+        self.prevotes.retain(|(height, _), _| *height > finalized_height);
+        self.precommits.retain(|_, votes| votes.iter().any(|vote| vote.height > finalized_height));
+    }
+}
+
+// --- Unit Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proposer_rotates_by_height_and_round() {
+        let validators: HashSet<String> = ["c", "a", "b"].iter().map(|v| v.to_string()).collect();
+
+        assert_eq!(proposer_for(&validators, 0, 0).unwrap(), "a");
+        assert_eq!(proposer_for(&validators, 1, 0).unwrap(), "b");
+        assert_eq!(proposer_for(&validators, 2, 0).unwrap(), "c");
+        assert_eq!(proposer_for(&validators, 3, 0).unwrap(), "a");
+        // A view change hands the height to the next validator.
+        assert_eq!(proposer_for(&validators, 1, 1).unwrap(), "c");
+        assert_eq!(proposer_for(&validators, 1, 2).unwrap(), "a");
+
+        assert!(proposer_for(&HashSet::new(), 1, 0).is_none());
+    }
+
+    #[test]
+    fn test_vote_signature_covers_height_and_round() {
+        let wallet = Wallet::new();
+        let validators: HashSet<String> = [wallet.public_key_hex()].into_iter().collect();
+        let vote = Vote::sign(&wallet, VoteKind::Prevote, 5, 1, "hash".to_string());
+
+        for forged in [Vote { height: 6, ..vote.clone() }, Vote { round: 2, ..vote.clone() }, Vote { kind: VoteKind::Precommit, ..vote.clone() }] {
+            let mut collector = VoteCollector::new();
+            assert!(matches!(collector.add_vote(forged, &validators, 1), VoteOutcome::Pending));
+        }
+        let mut collector = VoteCollector::new();
+        assert!(matches!(collector.add_vote(vote, &validators, 1), VoteOutcome::Polka { height: 5, round: 1, .. }));
+    }
+}
@@ -1,45 +1,371 @@
-use crate::blockchain::{Blockchain, AccountState};
-use crate::block::Transaction;
+use crate::blockchain::{Blockchain, AccountState, account_key, BLOCK_GAS_LIMIT};
+use crate::block::{Log, Receipt, Transaction, decode_ethereum_transaction};
 use crate::errors::NodeError;
 use revm::{
-    primitives::{AccountInfo, Bytecode, B160, U256 as RevmU256, TransactTo},
-    db::{CacheDB, EmptyDB},
-    EVM,
+    primitives::{Account, AccountInfo, Address as RevmAddress, Bytecode, Bytes, B256, HashMap as RevmHashMap, U256 as RevmU256, TransactTo, TxEnv, ExecutionResult, Output, KECCAK_EMPTY},
+    Database, DatabaseCommit, Evm,
 };
-use ethers_core::types::{Address, U256};
+use ethers_core::types::{Address, H256};
+use std::collections::HashMap;
 
-/// Processes a transaction using the EVM.
-/// This function will modify the state in the provided `CacheDB`.
+/// An EVM executing against a `StateDb`.
+pub type StateEvm<'a> = Evm<'a, (), StateDb<'a>>;
+
+/// A revm database backed by `Blockchain.state`.
+/// Reads fall through to the chain's accounts; writes are collected as a diff
+/// that the caller applies to the chain once execution is complete.
+pub struct StateDb<'a> {
+    blockchain: &'a Blockchain,
+    /// Accounts changed by committed transactions. `None` marks a deleted (self-destructed) account.
+    changes: HashMap<String, Option<AccountState>>,
+}
+
+impl<'a> StateDb<'a> {
+    pub fn new(blockchain: &'a Blockchain) -> Self {
+        Self {
+            blockchain,
+            changes: HashMap::new(),
+        }
+    }
+
+    /// Returns the current view of an account, including uncommitted changes.
+    fn account(&self, key: &str) -> Option<&AccountState> {
+        match self.changes.get(key) {
+            Some(changed) => changed.as_ref(),
+            None => self.blockchain.state.get(key),
+        }
+    }
+
+    /// Consumes the database and returns the accounts that were changed.
+    pub fn into_changes(self) -> HashMap<String, Option<AccountState>> {
+        self.changes
+    }
+}
+
+impl Database for StateDb<'_> {
+    type Error = NodeError;
+
+    fn basic(&mut self, address: RevmAddress) -> Result<Option<AccountInfo>, Self::Error> {
+        Ok(self.account(&account_key(&address.0)).map(|account| AccountInfo {
+            balance: account.balance,
+            nonce: account.nonce,
+            code_hash: account.bytecode.as_ref().map(Bytecode::hash_slow).unwrap_or(KECCAK_EMPTY),
+            code: account.bytecode.clone(),
+        }))
+    }
+
+    fn code_by_hash(&mut self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        if code_hash == KECCAK_EMPTY {
+            return Ok(Bytecode::new());
+        }
+        self.changes.values().flatten()
+            .chain(self.blockchain.state.values())
+            .filter_map(|account| account.bytecode.as_ref())
+            .find(|code| code.hash_slow() == code_hash)
+            .cloned()
+            .ok_or_else(|| NodeError::Blockchain(format!("Unknown code hash {:?}", code_hash)))
+    }
+
+    fn storage(&mut self, address: RevmAddress, index: RevmU256) -> Result<RevmU256, Self::Error> {
+        Ok(self.account(&account_key(&address.0))
+            .and_then(|account| account.storage.get(&index).copied())
+            .unwrap_or(RevmU256::ZERO))
+    }
+
+    fn block_hash(&mut self, number: RevmU256) -> Result<B256, Self::Error> {
+        let block = u64::try_from(number).ok().and_then(|height| self.blockchain.block_by_height(height));
+        Ok(block
+            .and_then(|block| hex::decode(block.calculate_header_hash()).ok())
+            .map(|bytes| B256::from_slice(&bytes))
+            .unwrap_or_default())
+    }
+}
+
+impl DatabaseCommit for StateDb<'_> {
+    fn commit(&mut self, changes: RevmHashMap<RevmAddress, Account>) {
+        for (address, account) in changes {
+            if !account.is_touched() {
+                continue;
+            }
+            let key = account_key(&address.0);
+            if account.is_selfdestructed() {
+                self.changes.insert(key, None);
+                continue;
+            }
+
+            // A created account starts out empty, even if an account at this address was
+            // self-destructed earlier; none of its old storage or code survives.
+            let mut state = if account.is_created() {
+                AccountState::default()
+            } else {
+                self.account(&key).cloned().unwrap_or_default()
+            };
+            state.nonce = account.info.nonce;
+            state.balance = account.info.balance;
+            if let Some(code) = account.info.code.filter(|code| !code.is_empty()) {
+                state.bytecode = Some(code);
+            }
+            for (slot, value) in account.storage {
+                if value.present_value == RevmU256::ZERO {
+                    state.storage.remove(&slot);
+                } else {
+                    state.storage.insert(slot, value.present_value);
+                }
+            }
+            self.changes.insert(key, Some(state));
+        }
+    }
+}
+
+/// Processes a transaction using the EVM and commits its effects to the `StateDb` the EVM is running on.
+/// Reverts and halts are valid outcomes recorded in the returned result; an error means the
+/// transaction could not be executed at all (bad nonce, insufficient funds, ...) and nothing was committed.
 pub fn process_transaction(
-    blockchain: &Blockchain,
     transaction: &Transaction,
-    evm: &mut EVM<CacheDB<EmptyDB>>,
-) -> Result<(), NodeError> {
-    
-    evm.tx_env.caller = transaction.sender.into();
-    evm.tx_env.nonce = Some(transaction.nonce);
+    evm: &mut StateEvm<'_>,
+) -> Result<ExecutionResult, NodeError> {
+    // Start from a clean environment so nothing leaks over from the previous transaction.
+    let tx = evm.tx_mut();
+    *tx = TxEnv::default();
+    tx.caller = RevmAddress::from(transaction.sender.0);
+    tx.nonce = Some(transaction.nonce);
+    tx.chain_id = Some(transaction.chain_id);
+    tx.gas_limit = transaction.gas_limit;
+    tx.gas_price = RevmU256::from_limbs(transaction.max_fee_per_gas.0);
 
     match &transaction.action {
         crate::block::TransactionAction::Transfer { recipient, amount } => {
-            evm.tx_env.transact_to = TransactTo::Call((*recipient).into());
-            evm.tx_env.value = RevmU256::from_limbs(amount.0);
-            evm.tx_env.data = bytes::Bytes::new();
+            tx.transact_to = TransactTo::Call(RevmAddress::from(recipient.0));
+            tx.value = RevmU256::from_limbs(amount.0);
+            tx.data = Bytes::new();
         }
         crate::block::TransactionAction::Call { to, data, value } => {
-            evm.tx_env.transact_to = match to {
-                Some(addr) => TransactTo::Call((*addr).into()),
-                None => TransactTo::Create,
+            tx.transact_to = match to {
+                Some(addr) => TransactTo::Call(RevmAddress::from(addr.0)),
+                None => TransactTo::Create(revm::primitives::CreateScheme::Create),
+            };
+            tx.data = data.clone().into();
+            tx.value = RevmU256::from_limbs(value.0);
+        }
+        crate::block::TransactionAction::Ethereum { raw } => {
+            let (typed, _) = decode_ethereum_transaction(raw, transaction.chain_id)?;
+            tx.transact_to = match typed.to_addr() {
+                Some(addr) => TransactTo::Call(RevmAddress::from(addr.0)),
+                None => TransactTo::Create(revm::primitives::CreateScheme::Create),
             };
-            evm.tx_env.data = data.clone().into();
-            evm.tx_env.value = RevmU256::from_limbs(value.0);
+            tx.data = typed.data().map(|data| data.to_vec().into()).unwrap_or_default();
+            tx.value = typed.value().map(|value| RevmU256::from_limbs(value.0)).unwrap_or_default();
         }
     }
-    
+
     // Execute the transaction.
-    let result = evm.transact_commit();
+    evm.transact_commit()
+        .map_err(|e| NodeError::Blockchain(format!("EVM transaction {} failed: {:?}", transaction.hash, e)))
+}
+
+/// Builds the receipt for a transaction from its execution result.
+pub fn build_receipt(transaction: &Transaction, block_id: u64, index: usize, result: ExecutionResult) -> Receipt {
+    let (success, logs, contract_address) = match &result {
+        ExecutionResult::Success { logs, output, .. } => {
+            let created = match output {
+                Output::Create(_, Some(address)) => Some(Address::from(address.0 .0)),
+                _ => None,
+            };
+            (true, logs.clone(), created)
+        }
+        _ => (false, Vec::new(), None),
+    };
+
+    Receipt {
+        tx_hash: transaction.hash.clone(),
+        block_id,
+        index,
+        success,
+        gas_used: result.gas_used(),
+        logs: logs.into_iter().map(|log| Log {
+            address: Address::from(log.address.0 .0),
+            topics: log.data.topics().iter().map(|topic| H256::from(topic.0)).collect(),
+            data: log.data.data.to_vec(),
+        }).collect(),
+        contract_address,
+    }
+}
+
+/// A read-only message call, as used by `eth_call` and `eth_estimateGas`.
+#[derive(Debug, Default)]
+pub struct CallRequest {
+    pub from: Option<RevmAddress>,
+    pub to: Option<RevmAddress>,
+    pub data: Bytes,
+    pub value: RevmU256,
+    pub gas_limit: Option<u64>,
+}
+
+/// Executes a call against the current chain state without committing anything.
+/// Nonce checks and gas fees are skipped, as Ethereum nodes do for `eth_call`.
+pub fn simulate_call(blockchain: &Blockchain, call: CallRequest) -> Result<ExecutionResult, NodeError> {
+    let mut evm = Evm::builder()
+        .with_db(StateDb::new(blockchain))
+        // The call carries this chain's id, which revm checks against the configured one.
+        .modify_cfg_env(|cfg| cfg.chain_id = blockchain.chain_id)
+        .modify_block_env(|block| {
+            block.number = RevmU256::from(blockchain.height() + 1);
+            block.timestamp = RevmU256::from(chrono::Utc::now().timestamp().max(0) as u64);
+            block.gas_limit = RevmU256::from(BLOCK_GAS_LIMIT);
+            block.basefee = RevmU256::ZERO;
+        })
+        .modify_tx_env(|tx| {
+            tx.caller = call.from.unwrap_or_default();
+            tx.transact_to = match call.to {
+                Some(addr) => TransactTo::Call(addr),
+                None => TransactTo::Create(revm::primitives::CreateScheme::Create),
+            };
+            tx.data = call.data;
+            tx.value = call.value;
+            tx.nonce = None;
+            tx.chain_id = Some(blockchain.chain_id);
+            tx.gas_limit = call.gas_limit.unwrap_or(BLOCK_GAS_LIMIT);
+        })
+        .build();
+
+    evm.transact()
+        .map(|result| result.result)
+        .map_err(|e| NodeError::Blockchain(format!("EVM call failed: {:?}", e)))
+}
+
+// --- Unit Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{TransactionAction, KOSHER_CHAIN_ID};
+    use crate::blockchain::tests::{chain_with, next_block};
+    use crate::wallet::Wallet;
+    use ethers_core::types::U256;
+    use ethers_core::utils::get_create2_address;
+    use revm::primitives::SpecId;
+
+    /// Runtime code that returns 42 as a 32-byte word.
+    const ANSWER_RUNTIME: [u8; 10] = [0x60, 0x2a, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3];
+
+    fn call(user: &Wallet, nonce: u64, to: Option<Address>, data: Vec<u8>) -> Transaction {
+        let action = TransactionAction::Call { to, data, value: U256::zero() };
+        Transaction::new_signed(user, KOSHER_CHAIN_ID, action, nonce, 200_000, U256::one())
+    }
+
+    fn succeed(evm: &mut StateEvm<'_>, tx: Transaction) -> ExecutionResult {
+        match process_transaction(&tx, evm).unwrap() {
+            result @ ExecutionResult::Success { .. } => result,
+            result => panic!("Transaction failed: {:?}", result),
+        }
+    }
+
+    /// Executes `transactions` in the next block and adds it to the chain.
+    fn mine(chain: &mut Blockchain, validator: &Wallet, transactions: Vec<Transaction>) -> Vec<Receipt> {
+        let count = transactions.len();
+        let block = next_block(chain, validator, transactions);
+        assert_eq!(block.transactions.len(), count);
+        let hashes: Vec<String> = block.transactions.iter().map(|tx| tx.hash.clone()).collect();
+        chain.validate_and_add_block(block).unwrap();
+        hashes.iter().map(|hash| chain.receipts[hash].clone()).collect()
+    }
+
+    fn slot(chain: &Blockchain, address: Address, index: u64) -> Option<RevmU256> {
+        chain.state[&account_key(&address.0)].storage.get(&RevmU256::from(index)).copied()
+    }
+
+    #[test]
+    fn test_deployed_code_is_found_by_hash_and_runs() {
+        let (validator, user) = (Wallet::new(), Wallet::new());
+        let mut chain = chain_with(&validator, &user);
+        // Copies the runtime code into memory and returns it.
+        let mut init = vec![0x69];
+        init.extend(ANSWER_RUNTIME);
+        init.extend([0x60, 0x00, 0x52, 0x60, 0x0a, 0x60, 0x16, 0xf3]);
+
+        let receipts = mine(&mut chain, &validator, vec![call(&user, 0, None, init)]);
+        let contract = receipts[0].contract_address.expect("Deployment creates a contract");
+        let runtime = Bytecode::new_raw(ANSWER_RUNTIME.to_vec().into());
+
+        let mut db = StateDb::new(&chain);
+        let info = db.basic(RevmAddress::from(contract.0)).unwrap().unwrap();
+        assert_eq!(info.code_hash, runtime.hash_slow());
+        assert_eq!(db.code_by_hash(info.code_hash).unwrap().original_bytes(), runtime.original_bytes());
+        assert!(db.code_by_hash(KECCAK_EMPTY).unwrap().is_empty());
+        assert!(db.code_by_hash(B256::repeat_byte(1)).is_err());
+
+        let request = CallRequest { to: Some(RevmAddress::from(contract.0)), ..Default::default() };
+        match simulate_call(&chain, request).unwrap() {
+            ExecutionResult::Success { output, .. } => assert_eq!(output.into_data().to_vec(), RevmU256::from(42).to_be_bytes::<32>().to_vec()),
+            result => panic!("Call failed: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_storage_written_by_one_transaction_is_read_by_the_next() {
+        let (validator, user) = (Wallet::new(), Wallet::new());
+        let mut chain = chain_with(&validator, &user);
+        // With calldata, stores its first word in slot 0. Without, copies slot 0 to slot 1 and returns it.
+        let runtime = vec![
+            0x36, 0x15, 0x60, 0x0c, 0x57, 0x60, 0x00, 0x35, 0x60, 0x00, 0x55, 0x00,
+            0x5b, 0x60, 0x00, 0x54, 0x80, 0x60, 0x01, 0x55, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3,
+        ];
+        let init_len = 12u8;
+        let mut init = vec![0x60, runtime.len() as u8, 0x60, init_len, 0x60, 0x00, 0x39, 0x60, runtime.len() as u8, 0x60, 0x00, 0xf3];
+        assert_eq!(init.len(), init_len as usize);
+        init.extend(&runtime);
+        let receipts = mine(&mut chain, &validator, vec![call(&user, 0, None, init)]);
+        let contract = receipts[0].contract_address.unwrap();
+
+        // The read in the second transaction sees the first one's uncommitted write.
+        let store = |nonce, value: u64| call(&user, nonce, Some(contract), RevmU256::from(value).to_be_bytes::<32>().to_vec());
+        let receipts = mine(&mut chain, &validator, vec![store(1, 7), call(&user, 2, Some(contract), vec![])]);
+        assert!(receipts.iter().all(|receipt| receipt.success));
+        assert_eq!((slot(&chain, contract, 0), slot(&chain, contract, 1)), (Some(RevmU256::from(7)), Some(RevmU256::from(7))));
+
+        // A later block reads the committed value, and writing zero clears the slot.
+        let receipts = mine(&mut chain, &validator, vec![store(3, 0), call(&user, 4, Some(contract), vec![])]);
+        assert!(receipts.iter().all(|receipt| receipt.success));
+        assert_eq!((slot(&chain, contract, 0), slot(&chain, contract, 1)), (None, None));
+    }
+
+    #[test]
+    fn test_selfdestruct_removes_account_and_create2_recreates_it_empty() {
+        let (validator, user) = (Wallet::new(), Wallet::new());
+        let chain = chain_with(&validator, &user);
+        // Deploys its calldata as init code with CREATE2 and salt 0.
+        let factory_runtime = vec![0x36, 0x60, 0x00, 0x60, 0x00, 0x37, 0x60, 0x00, 0x36, 0x60, 0x00, 0x60, 0x00, 0xf5, 0x00];
+        let mut factory_init = vec![0x60, factory_runtime.len() as u8, 0x60, 0x0c, 0x60, 0x00, 0x39, 0x60, factory_runtime.len() as u8, 0x60, 0x00, 0xf3];
+        factory_init.extend(&factory_runtime);
+        // Increments slot 0, then deploys runtime code that self-destructs when called.
+        let child_init = vec![0x60, 0x00, 0x54, 0x60, 0x01, 0x01, 0x60, 0x00, 0x55, 0x61, 0x33, 0xff, 0x60, 0x00, 0x52, 0x60, 0x02, 0x60, 0x1e, 0xf3];
+
+        // From Cancun on SELFDESTRUCT only deletes contracts created in the same transaction,
+        // so re-creating one at the same address needs the earlier rules.
+        let mut evm = Evm::builder()
+            .with_db(StateDb::new(&chain))
+            .modify_cfg_env(|cfg| cfg.chain_id = chain.chain_id)
+            .with_spec_id(SpecId::SHANGHAI)
+            .build();
+        let factory = match succeed(&mut evm, call(&user, 0, None, factory_init)) {
+            ExecutionResult::Success { output: Output::Create(_, Some(address)), .. } => Address::from(address.0 .0),
+            result => panic!("Factory not created: {:?}", result),
+        };
+        let child = get_create2_address(factory, [0u8; 32], &child_init);
+        let child_key = account_key(&child.0);
+
+        succeed(&mut evm, call(&user, 1, Some(factory), child_init.clone()));
+        let child_slot = |evm: &mut StateEvm| evm.db_mut().storage(RevmAddress::from(child.0), RevmU256::ZERO).unwrap();
+        assert_eq!(child_slot(&mut evm), RevmU256::from(1));
+
+        succeed(&mut evm, call(&user, 2, Some(child), vec![]));
+        assert!(evm.db_mut().changes[&child_key].is_none());
+        assert!(evm.db_mut().basic(RevmAddress::from(child.0)).unwrap().is_none());
+        assert_eq!(child_slot(&mut evm), RevmU256::ZERO);
 
-    match result {
-        Ok(_) => Ok(()),
-        Err(e) => Err(NodeError::Blockchain(format!("EVM transaction failed: {:?}", e))),
+        // The new incarnation counts from an empty slot 0 rather than the destroyed one's value.
+        succeed(&mut evm, call(&user, 3, Some(factory), child_init));
+        assert_eq!(child_slot(&mut evm), RevmU256::from(1));
+        let changes = evm.into_context().evm.inner.db.into_changes();
+        assert!(changes[&child_key].as_ref().is_some_and(|account| account.bytecode.is_some()));
     }
 }
@@ -0,0 +1,173 @@
+use crate::block::{Block, BlockHeader, KOSHER_CHAIN_ID};
+use crate::blockchain::{account_key, compute_state_root, AccountState, Blockchain};
+use crate::errors::NodeError;
+use ed25519_dalek::Signature;
+use ethers_core::types::{Address, Bytes, H256, U256};
+use revm::primitives::{Bytecode, U256 as RevmU256};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// The contents of `genesis.json`. Every node of a network must use the same file,
+/// since it determines the genesis block hash all later blocks build on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Genesis {
+    pub chain_id: u64,
+    /// Unix timestamp of the genesis block.
+    pub timestamp: i64,
+    /// Hex-encoded ed25519 public keys of the initial validators.
+    pub validators: Vec<String>,
+    /// Accounts that exist at genesis, including pre-deployed contracts.
+    #[serde(default)]
+    pub alloc: BTreeMap<Address, GenesisAccount>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GenesisAccount {
+    /// Balance as a hex quantity, e.g. `"0x3635c9adc5dea00000"`.
+    #[serde(default)]
+    pub balance: U256,
+    #[serde(default)]
+    pub nonce: u64,
+    /// Runtime bytecode of a pre-deployed contract, e.g. `HalachicRules`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<Bytes>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub storage: BTreeMap<H256, H256>,
+}
+
+impl Genesis {
+    /// A genesis with only a validator set, for chains configured with a bare validators file.
+    /// The timestamp is fixed so that every node derives the same genesis block.
+    pub fn with_validators(validators: HashSet<String>) -> Self {
+        let mut validators: Vec<String> = validators.into_iter().collect();
+        validators.sort();
+        Self {
+            chain_id: KOSHER_CHAIN_ID,
+            timestamp: 0,
+            validators,
+            alloc: BTreeMap::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, NodeError> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| NodeError::Config(format!("Failed to read genesis file {}: {}", path.display(), e)))?;
+        let genesis: Self = serde_json::from_str(&content)
+            .map_err(|e| NodeError::Config(format!("Failed to parse genesis file: {}", e)))?;
+
+        if genesis.validators.is_empty() {
+            return Err(NodeError::Config("Genesis file lists no validators".into()));
+        }
+        Ok(genesis)
+    }
+
+    pub fn validator_set(&self) -> HashSet<String> {
+        self.validators.iter().cloned().collect()
+    }
+
+    /// The accounts that exist at genesis, keyed as in `Blockchain.state`.
+    pub fn state(&self) -> HashMap<String, AccountState> {
+        self.alloc.iter()
+            .map(|(address, account)| {
+                let state = AccountState {
+                    nonce: account.nonce,
+                    balance: RevmU256::from_limbs(account.balance.0),
+                    bytecode: account.code.as_ref()
+                        .filter(|code| !code.is_empty())
+                        .map(|code| Bytecode::new_raw(code.to_vec().into())),
+                    storage: account.storage.iter()
+                        .map(|(slot, value)| (RevmU256::from_be_bytes(slot.0), RevmU256::from_be_bytes(value.0)))
+                        .collect(),
+                };
+                (account_key(&address.0), state)
+            })
+            .collect()
+    }
+
+    /// Builds the canonical genesis block. Its `previous_hash` is the hash of this genesis
+    /// spec, so the chain id and validator set are committed to along with the state root.
+    pub fn block(&self) -> Block {
+        let state = self.state();
+        let accounts = state.iter().map(|(key, account)| (key.as_str(), account)).collect();
+        Block {
+            header: BlockHeader {
+                id: 0,
+                round: 0,
+                timestamp: self.timestamp,
+                previous_hash: self.spec_hash(),
+                validator_pubkey: "system".to_string(),
+                transactions_hash: "0".repeat(64),
+                receipts_root: "0".repeat(64),
+                state_root: compute_state_root(&accounts),
+            },
+            transactions: vec![],
+            signature: Signature::from_bytes(&[0; 64]),
+            commit: vec![],
+        }
+    }
+
+    fn spec_hash(&self) -> String {
+        let mut spec = self.clone();
+        spec.validators.sort();
+        let data = serde_json::to_vec(&spec).expect("Genesis is always serializable");
+        hex::encode(Sha256::digest(data))
+    }
+
+    /// Checks that a loaded chain starts from this genesis. Chains restored from a
+    /// snapshot hold no genesis block and are checked against the snapshot's commit instead.
+    pub fn check(&self, chain: &Blockchain) -> Result<(), NodeError> {
+        let expected = self.block().calculate_header_hash();
+        match chain.block_by_height(0).map(Block::calculate_header_hash) {
+            Some(found) if found != expected => Err(NodeError::Config(format!(
+                "Stored chain has genesis {} but the genesis file produces {}. Is this the right data directory?",
+                found, expected
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+// --- Unit Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{Transaction, TransactionAction};
+    use crate::wallet::Wallet;
+
+    #[test]
+    fn test_genesis_block_is_canonical() {
+        let mut genesis = Genesis::with_validators(["b", "a"].iter().map(|v| v.to_string()).collect());
+        genesis.alloc.insert(Address::repeat_byte(1), GenesisAccount { balance: U256::from(1_000), ..Default::default() });
+
+        // Independent nodes derive the same block, regardless of validator order.
+        let mut reordered = genesis.clone();
+        reordered.validators.reverse();
+        assert_eq!(genesis.block().calculate_header_hash(), reordered.block().calculate_header_hash());
+
+        let chain = Blockchain::from_genesis(&genesis);
+        assert_eq!(chain.block_by_height(0).unwrap().header.state_root, chain.state_root());
+        assert_eq!(chain.state[&account_key(&[1; 20])].balance, RevmU256::from(1_000));
+        assert!(genesis.check(&chain).is_ok());
+
+        // A different allocation is a different chain.
+        let mut other = genesis.clone();
+        other.alloc.clear();
+        assert!(other.check(&chain).is_err());
+    }
+
+    #[test]
+    fn test_chain_id_comes_from_genesis() {
+        let mut genesis = Genesis::with_validators(["a"].iter().map(|v| v.to_string()).collect());
+        genesis.chain_id = 42;
+        assert_ne!(genesis.block().calculate_header_hash(), Genesis::with_validators(genesis.validator_set()).block().calculate_header_hash());
+
+        let chain = Blockchain::from_genesis(&genesis);
+        let wallet = Wallet::new();
+        let action = TransactionAction::Transfer { recipient: Address::repeat_byte(2), amount: U256::one() };
+        assert!(chain.is_transaction_valid(&Transaction::new_signed(&wallet, 42, action.clone(), 0, 21_000, U256::one())).is_ok());
+        assert!(chain.is_transaction_valid(&Transaction::new_signed(&wallet, KOSHER_CHAIN_ID, action, 0, 21_000, U256::one())).is_err());
+    }
+}
@@ -1,10 +1,9 @@
-use std::collections::{HashSet, HashMap};
-use std::fs;
 use std::sync::{Arc, Mutex};
 use std::path::Path;
 use tokio::sync::mpsc;
 use tokio::signal;
 use tracing::{info, error, warn};
+use clap::Parser;
 
 // Import all modules
 mod block;
@@ -16,251 +15,121 @@ mod xrpl_witness;
 mod errors;
 mod config;
 mod api;
+mod cli;
+mod commands;
+mod rpc;
 mod validator;
+mod consensus;
 mod persistence;
+mod evm;
+mod merkle;
+mod genesis;
+mod storage;
+mod snapshot;
+mod sync;
 mod logger; // New logger module
 
-use config::Config;
+use config::{Config, StorageBackend};
 use errors::NodeError;
 use wallet::Wallet;
 
+/// Capacity of the channels between the node's services.
+const CHANNEL_CAPACITY: usize = 256;
+
 #[tokio::main]
 async fn main() -> Result<(), NodeError> {
     // Initialize the logger as the first step.
     logger::init();
 
+    let cli = cli::Cli::parse();
+    let command = cli.command.unwrap_or(cli::Command::Node(cli::NodeCommand::Run));
+    commands::run(command, &cli.config, cli.data_dir).await
+}
+
+/// Starts all node services and runs until Ctrl-C.
+async fn run_node(config: Config) -> Result<(), NodeError> {
     info!("--- Kosher Chain Node Starting ---");
 
     // --- 1. Load Configuration ---
-    let config = Config::load("config.toml")?;
-    info!("Configuration loaded successfully.");
+    info!("Configuration loaded successfully. Data directory: {}", config.storage.data_dir.display());
 
-    // ... (State initialization logic remains the same) ...
-    let validators_content = fs::read_to_string(&config.chain.validators_file)?;
-    let validators_data: HashMap<String, Vec<String>> = serde_json::from_str(&validators_content)?;
-    let validator_set: HashSet<String> = validators_data.get("validators")
-        .expect("`validators` key not found in validators file")
-        .iter().cloned().collect();
-    let blockchain = Arc::new(Mutex::new(persistence::load_or_initialize_state(validator_set.clone())?));
-    
-    // ... (Task spawning logic remains the same, but they will now use tracing internally) ...
+    // --- 2. Shared State ---
+    let validator_set = config.chain.load_genesis()?.validator_set();
+    let chain = commands::open_chain(&config)?;
+    let blockchain = Arc::new(Mutex::new(chain));
 
-    // --- 5. Conditional Validator Service ---
-    if let Some(validator_config) = config.validator {
+    let mut mempool = mempool::Mempool::new();
+    if config.mempool.journal {
+        mempool.open_journal(&config.storage.data_dir, &blockchain.lock().unwrap())?;
+    }
+    let mempool = Arc::new(Mutex::new(mempool));
+    let peer_manager = Arc::new(Mutex::new(p2p::PeerManager::default()));
+    // Messages other services want gossiped to the network.
+    let (p2p_tx, p2p_rx) = mpsc::channel(CHANNEL_CAPACITY);
+
+    // --- 3. Conditional Validator Service ---
+    // Proposals and votes received by the P2P service are forwarded to the validator over this channel.
+    let mut consensus_tx = None;
+    let mut validator_task = None;
+    if let Some(validator_config) = &config.validator {
         info!("Validator config found. Attempting to start validator service...");
         let validator_wallet = Wallet::load_or_create(Path::new(&validator_config.key_file))?;
-        
+
         if validator_set.contains(&validator_wallet.public_key_hex()) {
             info!("✅ Wallet public key is in the official validator set.");
-            // ... spawn validator service ...
-        } else {
-            warn!("🚨 Wallet key is not in the validator set. Node will run in non-validating mode.");
-        }
-    } else {
-        info!("No validator config found. Running in non-validating mode.");
-    }
-
-    // --- 6. Graceful Shutdown ---
-    match signal::ctrl_c().await {
-        Ok(()) => {
-            info!("\nCtrl-C received. Shutting down node gracefully...");
-            
-            if let Err(e) = persistence::save_state(&blockchain.lock().unwrap()) {
-                error!("CRITICAL: Failed to save state on shutdown: {}", e);
-            }
-            
-            info!("All services stopped.");
-        }
-        Err(err) => {
-            error!("Unable to listen for shutdown signal: {}", err);
-        }
-    }
-    
-    Ok(())
-}            println!("\n[Main] Ctrl-C received. Shutting down node gracefully...");
-            
-            // Save the final state before exiting.
-            if let Err(e) = persistence::save_state(&blockchain.lock().unwrap()) {
-                eprintln!("[Main] CRITICAL: Failed to save state on shutdown: {}", e);
-            } else {
-                println!("[Main] Final state saved successfully.");
-            }
-            
-            api_task.abort();
-            p2p_task.abort();
-            // Abort other tasks...
-            println!("[Main] All services stopped.");
-        }
-        Err(err) => {
-            eprintln!("[Main] Unable to listen for shutdown signal: {}", err);
-        }
-    }
-    
-    Ok(())
-}        if validator_set.contains(&validator_wallet.public_key_hex()) {
-            println!("[Main] ✅ Wallet public key is in the official validator set.");
+            let (tx, consensus_rx) = mpsc::channel(CHANNEL_CAPACITY);
+            consensus_tx = Some(tx);
             let validator_service = validator::ValidatorService::new(
                 validator_wallet,
                 blockchain.clone(),
                 mempool.clone(),
                 p2p_tx.clone(),
+                consensus_rx,
             );
-            tokio::spawn(validator_service.run());
+            validator_task = Some(tokio::spawn(validator_service.run()));
         } else {
-            eprintln!("[Main] 🚨 WARNING: Wallet key is not in the validator set. Node will run in non-validating mode.");
+            warn!("🚨 Wallet key is not in the validator set. Node will run in non-validating mode.");
         }
     } else {
-        println!("[Main] No validator config found. Running in non-validating mode.");
+        info!("No validator config found. Running in non-validating mode.");
     }
-    
-    // --- 6. Graceful Shutdown ---
+
+    // --- 4. Network, API and Witness Services ---
+    let p2p_task = tokio::spawn(p2p::run_p2p_network(
+        config.p2p,
+        blockchain.clone(),
+        mempool.clone(),
+        peer_manager,
+        p2p_rx,
+        consensus_tx,
+    ));
+    let app_state = api::AppState { blockchain: blockchain.clone(), mempool, p2p_tx };
+    let api_task = tokio::spawn(api::run_api(config.api, app_state));
+    let witness_task = tokio::spawn(xrpl_witness::run_xrpl_witness(config.witness));
+
+    // --- 5. Graceful Shutdown ---
     match signal::ctrl_c().await {
         Ok(()) => {
-            println!("\n[Main] Ctrl-C received. Shutting down node gracefully...");
-            // Abort handles are not necessary if tasks gracefully exit on channel close/error
-            println!("[Main] All services stopped.");
-        }
-        Err(err) => {
-            eprintln!("[Main] Unable to listen for shutdown signal: {}", err);
-        }
-    }
-    
-    Ok(())
-}            witness_task.abort();
-            println!("[Main] All services stopped.");
-        }
-        Err(err) => {
-            eprintln!("[Main] Unable to listen for shutdown signal: {}", err);
-        }
-    }
-    
-    Ok(())
-}    }
-    
-    Ok(())
-}                            // e.g., penalize for duplicate or invalid transactions.
-                            println!("[Gossip] Received transaction {} from peer {}", tx.hash, source_peer);
-                            // mempool.lock().unwrap().add_transaction(tx);
-                        }
-                        Err(_) => {
-                            println!("[Gossip] ❌ Received malformed message from {}. Penalizing peer.", source_peer);
-                            let should_ban = peer_manager.lock().unwrap().penalize_peer(&source_peer, 25);
-                            if should_ban {
-                                swarm.ban_peer(source_peer);
-                            }
-                        }
-                    }
-                }
-                _ => {
-                    // Handle other events like Mdns discovery...
-                }
-            }
-        }
-    }
-}    println!("User state after tx: {:?}", kosher_chain.state.get(&user_wallet.public_key_hex()));
-
+            info!("\nCtrl-C received. Shutting down node gracefully...");
 
-    // 6. **DEMONSTRATE SECURITY**: Attacker tries to replay the same transaction in a new block
-    println!("\nAttacker attempting to replay nonce 0 transaction in a new block...");
-    let previous_block_2 = kosher_chain.blocks.last().unwrap();
-    let mut replay_block = Block {
-        header: BlockHeader {
-            id: previous_block_2.header.id + 1,
-            timestamp: 1,
-            previous_hash: previous_block_2.calculate_header_hash(),
-            validator_pubkey: validator_wallet.public_key_hex(),
-            transactions_hash: Block::hash_transactions(&vec![tx1]), // Re-using tx1
-        },
-        transactions: vec![tx1],
-        signature: ed25519_dalek::Signature::from_bytes(&[0; 64]).unwrap(),
-    };
-    let replay_block_hash = replay_block.calculate_header_hash();
-    replay_block.signature = validator_wallet.sign(replay_block_hash.as_bytes());
+            // Stop producing and importing blocks before the final state is written.
+            for task in [Some(p2p_task), Some(api_task), Some(witness_task), validator_task].into_iter().flatten() {
+                task.abort();
+            }
 
-    match kosher_chain.validate_and_add_block(replay_block) {
-        Ok(_) => eprintln!("❌ FAILURE: Replay attack succeeded! Security flaw!"),
-        Err(e) => println!("✅ SUCCESS: Chain correctly rejected the block. Reason: {}", e),
-    }
-    println!("User state (unchanged): {:?}", kosher_chain.state.get(&user_wallet.public_key_hex()));
-}                libp2p::swarm::SwarmEvent::Behaviour(ChainBehaviourEvent::Gossipsub(
-                    gossipsub::Event::Message { message, .. }
-                )) => {
-                    if let Ok(msg) = serde_json::from_slice::<ChainMessage>(&message.data) {
-                        match msg {
-                            ChainMessage::Block(block) => {
-                                // Block handling logic from Phase 4
-                            }
-                            ChainMessage::Transaction(tx) => {
-                                println!("Received new transaction via gossip: {}", tx.hash);
-                                mempool.lock().unwrap().add_transaction(tx);
-                            }
-                        }
-                    }
+            // The database has already committed every block; only the JSON backend saves on exit.
+            if config.storage.backend == StorageBackend::Json {
+                if let Err(e) = persistence::save_state(&blockchain.lock().unwrap(), &config.storage.data_dir) {
+                    error!("CRITICAL: Failed to save state on shutdown: {}", e);
                 }
-                _ => {}
             }
-        }
-    }
-}
-
-// --- API Server Logic ---
-async fn run_api(state: AppState) {
-    let app = Router::new()
-        .route("/transaction", post(handle_transaction))
-        .with_state(state);
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-    println!("API server listening on {}", addr);
-    axum::serve(axum::Server::bind(&addr), app).await.unwrap();
-}
-
-async fn handle_transaction(
-    State(state): State<AppState>,
-    Json(tx): Json<Transaction>,
-) -> StatusCode {
-    let mut mempool = state.mempool.lock().unwrap();
-    
-    // Add to local mempool
-    if mempool.add_transaction(tx.clone()) {
-        println!("Accepted new transaction via API: {}", tx.hash);
-        // Gossip to network
-        state.p2p_tx.send(ChainMessage::Transaction(tx)).await.unwrap();
-        StatusCode::OK
-    } else {
-        StatusCode::BAD_REQUEST // Transaction already exists
+            info!("All services stopped.");
+        }
+        Err(err) => {
+            error!("Unable to listen for shutdown signal: {}", err);
+        }
     }
-}
 
-/*
-NOTE: The `main` function is heavily simplified to show the structure. You would need
-to merge this logic with the full code from Phase 4, including P2P setup, user input
-for block creation, and the main event loop. The key change is adding the `mempool`
-to the shared state and handling the new `ChainMessage::Transaction` variant.
-
-The block creation logic (the "create" command) would now look like this:
-1. Lock the mempool.
-2. Call `mempool.get_transactions(10)` to get up to 10 pending transactions.
-3. Create a new block with these transactions.
-4. Gossip the new block.
-5. Call `mempool.clear(&transactions_in_block)` to remove them from the mempool.
-*/
-// ... other module imports
-mod xrpl_witness;
-
-// ... other use statements
-use xrpl_witness::run_xrpl_witness;
-
-#[tokio::main]
-async fn main() {
-    // --- 1. Setup Shared State ---
-    // ... all setup from Phase 5 ...
-
-    // --- 2. Start the API Server ---
-    tokio::spawn(run_api(app_state));
-
-    // --- 3. Start the XRPL Witness Service ---
-    tokio::spawn(run_xrpl_witness());
-
-    // --- 4. Setup and Run the P2P Swarm ---
-    // ... all P2P setup and main event loop from Phase 5 ...
+    Ok(())
 }
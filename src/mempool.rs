@@ -1,58 +1,660 @@
 use crate::block::Transaction;
-use std::collections::HashSet;
+use crate::blockchain::{account_key, Blockchain, BLOCK_GAS_LIMIT};
+use crate::errors::NodeError;
+use crate::persistence;
+use ethers_core::types::{Address, U256};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
 
 // The maximum number of transactions our mempool will hold.
 const MAX_MEMPOOL_SIZE: usize = 5000;
 
+/// The largest accepted transaction, measured as its JSON encoding. Leaves room for
+/// deploying a contract of the EVM's maximum code size.
+const MAX_TRANSACTION_SIZE: usize = 128 * 1024;
+
+/// How many pending transactions a single sender may have, so one account can't fill the pool.
+const MAX_TRANSACTIONS_PER_SENDER: usize = 64;
+
+/// How much higher, in percent, a replacement's fee must be than that of the transaction it replaces.
+const PRICE_BUMP_PERCENT: u64 = 10;
+
+/// How long a transaction may wait in the pool before it is dropped.
+const TRANSACTION_TTL: Duration = Duration::from_secs(3 * 60 * 60);
+
+/// The journal of locally submitted transactions inside the node's data directory, one JSON transaction per line.
+const JOURNAL_FILE: &str = "mempool_journal.jsonl";
+
+/// The pending transactions of one sender, keyed by nonce.
+#[derive(Debug, Default)]
+struct SenderQueue {
+    /// The sender's account nonce, as far as the mempool knows.
+    account_nonce: u64,
+    /// Transactions with consecutive nonces starting at `account_nonce`. They can be
+    /// executed in order on top of the current state.
+    ready: BTreeMap<u64, Transaction>,
+    /// Transactions behind a nonce gap. They become ready once the gap is filled.
+    future: BTreeMap<u64, Transaction>,
+}
+
+impl SenderQueue {
+    fn len(&self) -> usize {
+        self.ready.len() + self.future.len()
+    }
+
+    fn get(&self, nonce: u64) -> Option<&Transaction> {
+        self.ready.get(&nonce).or_else(|| self.future.get(&nonce))
+    }
+
+    /// All transactions in nonce order.
+    fn iter(&self) -> impl Iterator<Item = &Transaction> {
+        self.ready.values().chain(self.future.values())
+    }
+
+    /// The transaction with the highest nonce, the only one that can be removed without leaving a gap.
+    fn last(&self) -> Option<&Transaction> {
+        self.future.values().next_back().or_else(|| self.ready.values().next_back())
+    }
+
+    fn insert(&mut self, tx: Transaction) {
+        self.future.insert(tx.nonce, tx);
+        self.promote();
+    }
+
+    /// Swaps in a transaction for the one with the same nonce and returns the old one.
+    fn replace(&mut self, tx: Transaction) -> Option<Transaction> {
+        let queue = if self.ready.contains_key(&tx.nonce) { &mut self.ready } else { &mut self.future };
+        queue.insert(tx.nonce, tx)
+    }
+
+    /// Removes the transaction with `nonce` and every later one.
+    fn truncate(&mut self, nonce: u64) -> Vec<Transaction> {
+        let mut removed = self.ready.split_off(&nonce);
+        removed.append(&mut self.future.split_off(&nonce));
+        removed.into_values().collect()
+    }
+
+    /// Moves future transactions that now directly follow the ready ones into `ready`.
+    fn promote(&mut self) {
+        let mut next = self.account_nonce + self.ready.len() as u64;
+        while let Some(tx) = self.future.remove(&next) {
+            self.ready.insert(next, tx);
+            next += 1;
+        }
+    }
+
+    /// Moves the queue to a new account nonce and returns the transactions it makes stale.
+    fn rebase(&mut self, account_nonce: u64) -> Vec<Transaction> {
+        let mut pending = std::mem::take(&mut self.ready);
+        pending.append(&mut self.future);
+        self.future = pending.split_off(&account_nonce);
+        self.account_nonce = account_nonce;
+        self.promote();
+        pending.into_values().collect()
+    }
+}
+
+/// Pending transactions, queued per sender in nonce order.
 #[derive(Debug)]
 pub struct Mempool {
-    transactions: HashSet<Transaction>,
+    senders: HashMap<Address, SenderQueue>,
+    /// When each pooled transaction arrived, keyed by hash. Also used for deduplication.
+    added_at: HashMap<String, Instant>,
     max_size: usize,
+    /// Where locally submitted transactions are journaled, if enabled.
+    journal: Option<Journal>,
+    /// Hashes of pooled transactions that were submitted to this node rather than gossiped.
+    locals: HashSet<String>,
 }
 
-// Custom error type for adding a transaction to the mempool.
-#[derive(Debug, PartialEq)]
+/// Why a transaction was not admitted to the mempool. The messages follow the ones
+/// Ethereum clients use, since wallets and tooling match on them.
+#[derive(Error, Debug, PartialEq)]
 pub enum MempoolError {
-    PoolFull,
+    #[error("already known")]
     AlreadyExists,
+    #[error("transaction hash does not match its contents")]
+    InvalidHash,
+    #[error("invalid chain id {0}")]
+    WrongChainId(u64),
+    #[error("invalid sender: {0}")]
+    InvalidSignature(String),
+    #[error("oversized data: {size} bytes, limit {MAX_TRANSACTION_SIZE}")]
+    Oversized { size: usize },
+    #[error("exceeds block gas limit")]
+    GasLimitTooHigh,
+    /// The sender's account has already used this nonce.
+    #[error("nonce too low: next nonce {account_nonce}, tx nonce {nonce}")]
+    NonceTooLow { account_nonce: u64, nonce: u64 },
+    #[error("insufficient funds for gas * price + value: balance {balance}, tx cost {cost}")]
+    InsufficientFunds { balance: U256, cost: U256 },
+    /// The pool is full and the transaction pays no more than anything it could evict.
+    #[error("transaction underpriced")]
+    Underpriced,
+    /// A transaction with the same sender and nonce is pooled and the fee bump is too small.
+    #[error("replacement transaction underpriced: fee must be at least {required}")]
+    ReplacementUnderpriced { required: U256 },
+    #[error("account limit exceeded: at most {MAX_TRANSACTIONS_PER_SENDER} pending transactions per sender")]
+    AccountLimitExceeded,
+}
+
+impl MempoolError {
+    /// Whether the transaction can never be valid, as opposed to being rejected
+    /// because of this node's current state. Only the former is a peer's fault.
+    pub fn is_invalid(&self) -> bool {
+        !matches!(
+            self,
+            Self::AlreadyExists
+                | Self::NonceTooLow { .. }
+                | Self::InsufficientFunds { .. }
+                | Self::Underpriced
+                | Self::ReplacementUnderpriced { .. }
+                | Self::AccountLimitExceeded
+        )
+    }
 }
 
 impl Mempool {
     pub fn new() -> Self {
         Self {
-            transactions: HashSet::new(),
+            senders: HashMap::new(),
+            added_at: HashMap::new(),
             max_size: MAX_MEMPOOL_SIZE,
+            journal: None,
+            locals: HashSet::new(),
+        }
+    }
+
+    /// Enables the journal in `data_dir`. Transactions journaled before a restart are
+    /// re-validated against `chain` and pooled again; the journal is then rewritten
+    /// with only the ones that are still valid.
+    pub fn open_journal(&mut self, data_dir: &Path, chain: &Blockchain) -> Result<(), NodeError> {
+        let path = data_dir.join(JOURNAL_FILE);
+        let (mut restored, mut dropped) = (0, 0);
+        if path.exists() {
+            for line in fs::read_to_string(&path)?.lines() {
+                // A line torn by a crash is dropped like any other invalid entry.
+                let restored_tx = serde_json::from_str::<Transaction>(line).ok()
+                    .and_then(|tx| self.add_transaction(tx.clone(), chain).ok().map(|()| tx.hash));
+                match restored_tx {
+                    Some(hash) => {
+                        self.locals.insert(hash);
+                        restored += 1;
+                    }
+                    None => dropped += 1,
+                }
+            }
         }
+
+        write_journal(&path, &self.local_transactions())?;
+        self.journal = Some(Journal::spawn(path));
+        println!("[Mempool] Restored {} journaled transactions, dropped {} that are no longer valid.", restored, dropped);
+        Ok(())
     }
-    
-    /// Adds a transaction to the mempool, enforcing size limits.
-    pub fn add_transaction(&mut self, tx: Transaction) -> Result<(), MempoolError> {
-        if self.transactions.len() >= self.max_size {
-            return Err(MempoolError::PoolFull);
+
+    /// Adds a transaction submitted to this node, as `add_transaction` does,
+    /// and records it in the journal if one is enabled.
+    pub fn add_local_transaction(&mut self, tx: Transaction, chain: &Blockchain) -> Result<(), MempoolError> {
+        let hash = tx.hash.clone();
+        let journaled = self.journal.is_some().then(|| tx.clone());
+        self.add_transaction(tx, chain)?;
+        if let (Some(journal), Some(tx)) = (&self.journal, journaled) {
+            journal.send(JournalWrite::Append(Box::new(tx)));
         }
-        
-        if !self.transactions.insert(tx) {
+        self.locals.insert(hash);
+        Ok(())
+    }
+
+    /// Validates a transaction against `chain` and adds it to its sender's queue, enforcing
+    /// size limits. It is ready if it continues the sender's nonce sequence on `chain`,
+    /// and queued as future otherwise. A transaction with a pooled sender and nonce replaces
+    /// the pooled one if it pays at least `PRICE_BUMP_PERCENT` more. When the pool is full,
+    /// the cheapest transaction is evicted to make room for one that pays more.
+    pub fn add_transaction(&mut self, tx: Transaction, chain: &Blockchain) -> Result<(), MempoolError> {
+        // Deduplication relies on the hash, so it must be the one derived from the body.
+        if !tx.is_hash_valid() {
+            return Err(MempoolError::InvalidHash);
+        }
+        if self.added_at.contains_key(&tx.hash) {
             return Err(MempoolError::AlreadyExists);
         }
-        
+        check_stateless(&tx, chain.chain_id)?;
+        chain.is_transaction_valid(&tx).map_err(|e| MempoolError::InvalidSignature(e.to_string()))?;
+
+        let account = chain.state.get(&account_key(&tx.sender.0));
+        let account_nonce = account.map(|account| account.nonce).unwrap_or_default();
+        let balance = account.map(|account| U256(*account.balance.as_limbs())).unwrap_or_default();
+
+        // A sender's queue is only created once one of its transactions is admitted.
+        let queue_nonce = match self.senders.get_mut(&tx.sender) {
+            Some(queue) => {
+                if account_nonce > queue.account_nonce {
+                    for stale in queue.rebase(account_nonce) {
+                        self.added_at.remove(&stale.hash);
+                    }
+                }
+                queue.account_nonce
+            }
+            None => account_nonce,
+        };
+        if tx.nonce < queue_nonce {
+            return Err(MempoolError::NonceTooLow { account_nonce: queue_nonce, nonce: tx.nonce });
+        }
+
+        // The sender must also be able to pay for its pooled transactions that execute first.
+        let queue = self.senders.get(&tx.sender);
+        let cost = queue.into_iter()
+            .flat_map(SenderQueue::iter)
+            .take_while(|pooled| pooled.nonce < tx.nonce)
+            .fold(tx.max_cost(), |total, pooled| total.saturating_add(pooled.max_cost()));
+        if balance < cost {
+            return Err(MempoolError::InsufficientFunds { balance, cost });
+        }
+
+        if let Some(pooled) = queue.and_then(|queue| queue.get(tx.nonce)) {
+            let required = bumped_fee(pooled.max_fee_per_gas);
+            if tx.max_fee_per_gas < required {
+                return Err(MempoolError::ReplacementUnderpriced { required });
+            }
+            self.added_at.insert(tx.hash.clone(), Instant::now());
+            let replaced = self.senders.get_mut(&tx.sender).and_then(|queue| queue.replace(tx));
+            if let Some(replaced) = replaced {
+                self.added_at.remove(&replaced.hash);
+            }
+            return Ok(());
+        }
+        if queue.map_or(0, SenderQueue::len) >= MAX_TRANSACTIONS_PER_SENDER {
+            return Err(MempoolError::AccountLimitExceeded);
+        }
+        if self.added_at.len() >= self.max_size {
+            self.make_room(&tx)?;
+        }
+
+        self.added_at.insert(tx.hash.clone(), Instant::now());
+        self.senders.entry(tx.sender)
+            .or_insert_with(|| SenderQueue { account_nonce: queue_nonce, ..Default::default() })
+            .insert(tx);
         Ok(())
     }
 
-    /// Returns a vector of transactions to be included in a block.
+    /// Drops transactions that have waited longer than `TRANSACTION_TTL`, together with
+    /// the later transactions of the same sender, which can't execute without them.
+    pub fn remove_expired(&mut self) {
+        let Some(cutoff) = Instant::now().checked_sub(TRANSACTION_TTL) else { return };
+        let expired: Vec<(Address, u64)> = self.senders.iter()
+            .filter_map(|(sender, queue)| {
+                queue.iter().find(|tx| self.added_at[&tx.hash] < cutoff).map(|tx| (*sender, tx.nonce))
+            })
+            .collect();
+        for (sender, nonce) in expired {
+            self.remove_from(sender, nonce);
+        }
+    }
+
+    /// Frees a slot in a full pool for `tx`. Expired transactions go first, then the
+    /// cheapest (and among those, the oldest) transaction that `tx` outbids.
+    fn make_room(&mut self, tx: &Transaction) -> Result<(), MempoolError> {
+        self.remove_expired();
+        if self.added_at.len() < self.max_size {
+            return Ok(());
+        }
+
+        // Evicting anything but the last transaction of a queue would leave a nonce gap.
+        let cheapest = self.senders.values()
+            .filter_map(SenderQueue::last)
+            .min_by_key(|last| (last.max_fee_per_gas, self.added_at[&last.hash]))
+            .map(|last| (last.sender, last.nonce, last.max_fee_per_gas));
+        match cheapest {
+            Some((sender, nonce, fee)) if fee < tx.max_fee_per_gas => {
+                self.remove_from(sender, nonce);
+                Ok(())
+            }
+            _ => Err(MempoolError::Underpriced),
+        }
+    }
+
+    /// Removes a sender's transaction with `nonce` and every later one.
+    fn remove_from(&mut self, sender: Address, nonce: u64) {
+        let Some(queue) = self.senders.get_mut(&sender) else { return };
+        for tx in queue.truncate(nonce) {
+            self.added_at.remove(&tx.hash);
+        }
+    }
+
+    /// Returns up to `count` ready transactions for a block, highest fee first.
+    /// Each sender's transactions come out in nonce order without gaps, so the
+    /// result can be executed as a sequence.
     pub fn get_transactions(&self, count: usize) -> Vec<Transaction> {
-        self.transactions.iter().take(count).cloned().collect()
+        // Each sender competes with its lowest ready nonce; taking it exposes the next one.
+        let mut candidates: BinaryHeap<(U256, Reverse<Address>, u64)> = self.senders.iter()
+            .filter_map(|(sender, queue)| queue.ready.values().next().map(|tx| (tx.max_fee_per_gas, Reverse(*sender), tx.nonce)))
+            .collect();
+
+        let mut selected = Vec::with_capacity(count.min(self.added_at.len()));
+        while selected.len() < count {
+            let Some((_, Reverse(sender), nonce)) = candidates.pop() else { break };
+            let queue = &self.senders[&sender];
+            selected.push(queue.ready[&nonce].clone());
+            if let Some(next) = queue.ready.get(&(nonce + 1)) {
+                candidates.push((next.max_fee_per_gas, Reverse(sender), next.nonce));
+            }
+        }
+        selected
     }
-    
-    /// Removes transactions that have been included in a block.
-    pub fn clear(&mut self, transactions_to_remove: &[Transaction]) {
-        for tx in transactions_to_remove {
-            self.transactions.remove(tx);
+
+    /// Brings the pool up to date with `chain` after blocks were imported or reverted.
+    /// Included transactions and others whose nonce is now used are dropped, as is every
+    /// transaction from the first one whose cost, together with the sender's earlier pooled
+    /// transactions, exceeds the sender's balance. Future transactions whose nonce gap was closed become ready.
+    pub fn revalidate(&mut self, chain: &Blockchain) {
+        for (sender, queue) in self.senders.iter_mut() {
+            let account = chain.state.get(&account_key(&sender.0));
+            let account_nonce = account.map(|account| account.nonce).unwrap_or_default();
+            let balance = account.map(|account| U256(*account.balance.as_limbs())).unwrap_or_default();
+
+            let mut dropped = queue.rebase(account_nonce);
+            let mut cost = U256::zero();
+            let unaffordable = queue.iter().find(|tx| {
+                cost = cost.saturating_add(tx.max_cost());
+                cost > balance
+            });
+            if let Some(nonce) = unaffordable.map(|tx| tx.nonce) {
+                dropped.extend(queue.truncate(nonce));
+            }
+            for tx in dropped {
+                self.added_at.remove(&tx.hash);
+            }
         }
+        // The chain knows the nonces of senders with nothing pending.
+        self.senders.retain(|_, queue| queue.len() > 0);
+        self.remove_expired();
+
+        let local_count = self.locals.len();
+        self.locals.retain(|hash| self.added_at.contains_key(hash));
+        if self.locals.len() < local_count {
+            if let Some(journal) = &self.journal {
+                journal.send(JournalWrite::Rewrite(self.local_transactions()));
+            }
+        }
+    }
+
+    /// The local transactions still pooled, in nonce order per sender.
+    fn local_transactions(&self) -> Vec<Transaction> {
+        self.senders.values()
+            .flat_map(SenderQueue::iter)
+            .filter(|tx| self.locals.contains(&tx.hash))
+            .cloned()
+            .collect()
     }
 }
 
+/// A change to the journal file.
+#[derive(Debug)]
+enum JournalWrite {
+    Append(Box<Transaction>),
+    /// Replaces the whole journal with these transactions.
+    Rewrite(Vec<Transaction>),
+}
+
+/// Writes the journal on a background thread, so the disk is never touched while the
+/// chain and mempool locks are held. Dropping it waits for pending writes to finish.
+#[derive(Debug)]
+struct Journal {
+    sender: Option<mpsc::Sender<JournalWrite>>,
+    writer: Option<thread::JoinHandle<()>>,
+}
+
+impl Journal {
+    fn spawn(path: PathBuf) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let writer = thread::spawn(move || {
+            for write in receiver {
+                // A transaction is pooled either way; if journaling fails it just won't survive a restart.
+                let result = match &write {
+                    JournalWrite::Append(tx) => append_to_journal(&path, tx),
+                    JournalWrite::Rewrite(transactions) => write_journal(&path, transactions),
+                };
+                if let Err(e) = result {
+                    eprintln!("[Mempool] Warning: failed to write the transaction journal: {}", e);
+                }
+            }
+        });
+        Self { sender: Some(sender), writer: Some(writer) }
+    }
+
+    fn send(&self, write: JournalWrite) {
+        if let Some(sender) = &self.sender {
+            // The writer only stops once the sender is dropped.
+            let _ = sender.send(write);
+        }
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// Replaces the journal with `transactions`.
+fn write_journal(path: &Path, transactions: &[Transaction]) -> Result<(), NodeError> {
+    let mut data = String::new();
+    for tx in transactions {
+        data.push_str(&serde_json::to_string(tx)?);
+        data.push('\n');
+    }
+    persistence::write_atomically(path, data.as_bytes())?;
+    Ok(())
+}
+
+fn append_to_journal(path: &Path, tx: &Transaction) -> Result<(), NodeError> {
+    let line = format!("{}\n", serde_json::to_string(tx)?);
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(line.as_bytes())?;
+    file.sync_data()?;
+    Ok(())
+}
+
+/// The lowest fee a replacement for a transaction paying `fee` must offer.
+fn bumped_fee(fee: U256) -> U256 {
+    (fee.saturating_mul(U256::from(100 + PRICE_BUMP_PERCENT)) / 100).max(fee.saturating_add(U256::one()))
+}
+
+/// Checks that don't depend on the chain: chain id, size and gas limit.
+fn check_stateless(tx: &Transaction, chain_id: u64) -> Result<(), MempoolError> {
+    if tx.chain_id != chain_id {
+        return Err(MempoolError::WrongChainId(tx.chain_id));
+    }
+    let size = serde_json::to_vec(tx).map(|data| data.len()).unwrap_or(usize::MAX);
+    if size > MAX_TRANSACTION_SIZE {
+        return Err(MempoolError::Oversized { size });
+    }
+    if tx.gas_limit > BLOCK_GAS_LIMIT {
+        return Err(MempoolError::GasLimitTooHigh);
+    }
+    Ok(())
+}
+
 impl Default for Mempool {
     fn default() -> Self {
         Self::new()
     }
 }
+
+// --- Unit Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{TransactionAction, KOSHER_CHAIN_ID};
+    use crate::blockchain::tests::next_block;
+    use crate::blockchain::ImportOutcome;
+    use crate::genesis::{Genesis, GenesisAccount};
+    use crate::wallet::Wallet;
+
+    fn transfer(wallet: &Wallet, nonce: u64, fee: u64) -> Transaction {
+        let action = TransactionAction::Transfer { recipient: Address::repeat_byte(0x22), amount: U256::one() };
+        Transaction::new_signed(wallet, KOSHER_CHAIN_ID, action, nonce, 21_000, U256::from(fee))
+    }
+
+    /// A chain where each wallet can pay for a few transfers at a fee of 100.
+    fn funded_chain(wallets: &[&Wallet]) -> Blockchain {
+        let mut genesis = Genesis::with_validators(HashSet::new());
+        for wallet in wallets {
+            genesis.alloc.insert(wallet.address(), GenesisAccount { balance: U256::from(10_000_000), ..Default::default() });
+        }
+        Blockchain::from_genesis(&genesis)
+    }
+
+    #[test]
+    fn test_selection_follows_nonces_and_fees() {
+        let (alice, bob) = (Wallet::new(), Wallet::new());
+        let chain = funded_chain(&[&alice, &bob]);
+        let mut mempool = Mempool::new();
+
+        // Alice's nonce 2 waits behind a gap, however high its fee.
+        mempool.add_transaction(transfer(&alice, 0, 1), &chain).unwrap();
+        mempool.add_transaction(transfer(&alice, 2, 100), &chain).unwrap();
+        mempool.add_transaction(transfer(&bob, 0, 5), &chain).unwrap();
+        let order: Vec<_> = mempool.get_transactions(10).iter().map(|tx| (tx.sender, tx.nonce)).collect();
+        assert_eq!(order, vec![(bob.address(), 0), (alice.address(), 0)]);
+
+        // Filling the gap promotes nonce 2.
+        mempool.add_transaction(transfer(&alice, 1, 1), &chain).unwrap();
+        assert_eq!(mempool.get_transactions(10).len(), 4);
+
+        // A block mines Bob's transaction and leaves Alice able to pay for two fee 1 transfers only.
+        let mut chain = chain;
+        chain.state.get_mut(&account_key(&bob.address().0)).unwrap().nonce = 1;
+        chain.state.get_mut(&account_key(&alice.address().0)).unwrap().balance = revm::primitives::U256::from(42_002);
+        mempool.revalidate(&chain);
+        assert_eq!(
+            mempool.add_transaction(transfer(&bob, 0, 9), &chain),
+            Err(MempoolError::NonceTooLow { account_nonce: 1, nonce: 0 })
+        );
+        let nonces: Vec<_> = mempool.get_transactions(10).iter().map(|tx| tx.nonce).collect();
+        assert_eq!(nonces, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_admission_rejects_invalid_transactions() {
+        let (alice, mallory) = (Wallet::new(), Wallet::new());
+        let chain = funded_chain(&[&alice]);
+        let mut mempool = Mempool::new();
+
+        // Mallory has no funds.
+        assert!(matches!(mempool.add_transaction(transfer(&mallory, 0, 1), &chain), Err(MempoolError::InsufficientFunds { .. })));
+        assert!(matches!(mempool.add_transaction(transfer(&alice, 0, 1_000), &chain), Err(MempoolError::InsufficientFunds { .. })));
+        // Rejected transactions leave no trace of their sender.
+        assert!(mempool.senders.is_empty());
+
+        // Alice can pay for four transfers at a fee of 100, but not for a fifth on top of them.
+        for nonce in 0..4 {
+            mempool.add_transaction(transfer(&alice, nonce, 100), &chain).unwrap();
+        }
+        assert_eq!(
+            mempool.add_transaction(transfer(&alice, 4, 100), &chain),
+            Err(MempoolError::InsufficientFunds { balance: U256::from(10_000_000), cost: U256::from(5 * 2_100_001) })
+        );
+        mempool = Mempool::new();
+
+        // A transaction re-signed by Mallory in Alice's name.
+        let mut forged = transfer(&mallory, 0, 1);
+        forged.sender = alice.address();
+        forged.hash = forged.compute_hash();
+        forged.signature = mallory.sign(forged.hash.as_bytes());
+        let rejected = mempool.add_transaction(forged, &chain).unwrap_err();
+        assert!(matches!(rejected, MempoolError::InvalidSignature(_)));
+        assert!(rejected.is_invalid());
+
+        let mut oversized = transfer(&alice, 0, 1);
+        oversized.action = TransactionAction::Call { to: None, data: vec![0xff; MAX_TRANSACTION_SIZE], value: U256::zero() };
+        oversized.hash = oversized.compute_hash();
+        assert!(matches!(mempool.add_transaction(oversized, &chain), Err(MempoolError::Oversized { .. })));
+
+        assert_eq!(mempool.add_transaction(transfer(&alice, 0, 1), &chain), Ok(()));
+    }
+
+    #[test]
+    fn test_replacement_and_eviction() {
+        let (alice, bob, carol) = (Wallet::new(), Wallet::new(), Wallet::new());
+        let chain = funded_chain(&[&alice, &bob, &carol]);
+        let mut mempool = Mempool::new();
+        mempool.max_size = 2;
+
+        // Replacing needs a 10% higher fee.
+        mempool.add_transaction(transfer(&alice, 0, 20), &chain).unwrap();
+        assert_eq!(
+            mempool.add_transaction(transfer(&alice, 0, 21), &chain),
+            Err(MempoolError::ReplacementUnderpriced { required: U256::from(22) })
+        );
+        mempool.add_transaction(transfer(&alice, 0, 22), &chain).unwrap();
+        assert_eq!(mempool.get_transactions(10)[0].max_fee_per_gas, U256::from(22));
+
+        // A full pool only admits transactions that outbid its cheapest one, which is evicted.
+        mempool.add_transaction(transfer(&bob, 0, 30), &chain).unwrap();
+        assert_eq!(mempool.add_transaction(transfer(&carol, 0, 22), &chain), Err(MempoolError::Underpriced));
+        mempool.add_transaction(transfer(&carol, 0, 23), &chain).unwrap();
+        let senders: HashSet<_> = mempool.get_transactions(10).iter().map(|tx| tx.sender).collect();
+        assert_eq!(senders, HashSet::from([bob.address(), carol.address()]));
+    }
+
+    #[test]
+    fn test_journal_survives_restart() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let alice = Wallet::new();
+        let chain = funded_chain(&[&alice]);
+
+        let mut mempool = Mempool::new();
+        mempool.open_journal(dir.path(), &chain).unwrap();
+        mempool.add_local_transaction(transfer(&alice, 0, 1), &chain).unwrap();
+        mempool.add_local_transaction(transfer(&alice, 1, 1), &chain).unwrap();
+
+        // After a restart, only the transaction that wasn't mined in the meantime comes back.
+        drop(mempool);
+        let mut chain = chain;
+        chain.state.get_mut(&account_key(&alice.address().0)).unwrap().nonce = 1;
+        let mut restarted = Mempool::new();
+        restarted.open_journal(dir.path(), &chain).unwrap();
+        let nonces: Vec<_> = restarted.get_transactions(10).iter().map(|tx| tx.nonce).collect();
+        assert_eq!(nonces, vec![1]);
+        assert_eq!(fs::read_to_string(dir.path().join(JOURNAL_FILE)).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn test_imported_block_clears_included_and_invalidated_transactions() {
+        let (validator, alice, carol) = (Wallet::new(), Wallet::new(), Wallet::new());
+        let mut genesis = Genesis::with_validators([validator.public_key_hex()].into_iter().collect());
+        for wallet in [&alice, &carol] {
+            genesis.alloc.insert(wallet.address(), GenesisAccount { balance: U256::from(10_000_000), ..Default::default() });
+        }
+        let mut chain = Blockchain::from_genesis(&genesis);
+        let mut mempool = Mempool::new();
+        let alice_txs: Vec<Transaction> = (0..3).map(|nonce| transfer(&alice, nonce, 1)).collect();
+        for tx in alice_txs.iter().cloned().chain([transfer(&carol, 0, 1), transfer(&carol, 1, 100)]) {
+            mempool.add_transaction(tx, &chain).unwrap();
+        }
+
+        // The block includes two of Alice's transactions and a different transaction from Carol
+        // with her first nonce, which spends so much that her pooled second one can't be paid for.
+        let spend = TransactionAction::Transfer { recipient: Address::repeat_byte(0x33), amount: U256::from(9_000_000) };
+        let carol_spend = Transaction::new_signed(&carol, KOSHER_CHAIN_ID, spend, 0, 21_000, U256::one());
+        let block = next_block(&chain, &validator, vec![alice_txs[0].clone(), alice_txs[1].clone(), carol_spend]);
+        assert_eq!(block.transactions.len(), 3);
+        assert!(matches!(chain.import_block(block), Ok(ImportOutcome::Extended)));
+
+        mempool.revalidate(&chain);
+        assert_eq!(mempool.get_transactions(10), vec![alice_txs[2].clone()]);
+        assert_eq!(mempool.added_at.len(), 1);
+        assert!(!mempool.senders.contains_key(&carol.address()));
+    }
+}
@@ -0,0 +1,156 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Sha256, Digest};
+
+/// A 32-byte Merkle tree hash.
+pub type MerkleHash = [u8; 32];
+
+// Domain separation prefixes, so a leaf can never be passed off as an inner node.
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// Hashes raw leaf data into a tree leaf.
+pub fn hash_leaf(data: &[u8]) -> MerkleHash {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+fn hash_node(left: &MerkleHash, right: &MerkleHash) -> MerkleHash {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Computes the root of a binary Merkle tree over already-hashed leaves.
+/// An odd node at the end of a level is promoted unchanged to the next level.
+/// The root of an empty tree is all zeros.
+pub fn merkle_root(leaves: &[MerkleHash]) -> MerkleHash {
+    if leaves.is_empty() {
+        return [0; 32];
+    }
+
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => hash_node(left, right),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0]
+}
+
+/// Which side of the path a sibling hash sits on.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ProofStep {
+    /// Hex-encoded sibling hash.
+    pub hash: String,
+    pub side: Side,
+}
+
+/// A proof that a leaf is included in a Merkle tree, listing the sibling
+/// hashes from the leaf up to the root. Hashes are hex-encoded.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MerkleProof {
+    pub leaf: String,
+    pub index: usize,
+    pub siblings: Vec<ProofStep>,
+}
+
+/// Builds an inclusion proof for the leaf at `index`, or `None` if it is out of range.
+pub fn inclusion_proof(leaves: &[MerkleHash], index: usize) -> Option<MerkleProof> {
+    let leaf = *leaves.get(index)?;
+    let mut siblings = Vec::new();
+    let mut level = leaves.to_vec();
+    let mut position = index;
+
+    while level.len() > 1 {
+        let sibling = if position.is_multiple_of(2) {
+            level.get(position + 1).map(|hash| (hash, Side::Right))
+        } else {
+            Some((&level[position - 1], Side::Left))
+        };
+        // A promoted odd node has no sibling at this level.
+        if let Some((hash, side)) = sibling {
+            siblings.push(ProofStep { hash: hex::encode(hash), side });
+        }
+
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => hash_node(left, right),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+        position /= 2;
+    }
+
+    Some(MerkleProof { leaf: hex::encode(leaf), index, siblings })
+}
+
+/// Checks that `proof` leads from its leaf to the hex-encoded `root`.
+pub fn verify_proof(root: &str, proof: &MerkleProof) -> bool {
+    let Some(mut current) = decode_hash(&proof.leaf) else { return false };
+    for step in &proof.siblings {
+        let Some(sibling) = decode_hash(&step.hash) else { return false };
+        current = match step.side {
+            Side::Left => hash_node(&sibling, &current),
+            Side::Right => hash_node(&current, &sibling),
+        };
+    }
+    hex::encode(current) == root
+}
+
+fn decode_hash(hash_hex: &str) -> Option<MerkleHash> {
+    hex::decode(hash_hex).ok()?.try_into().ok()
+}
+
+// --- Unit Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(count: usize) -> Vec<MerkleHash> {
+        (0..count).map(|i| hash_leaf(&i.to_be_bytes())).collect()
+    }
+
+    #[test]
+    fn test_every_leaf_has_a_valid_proof() {
+        for count in 1..=9 {
+            let leaves = leaves(count);
+            let root = hex::encode(merkle_root(&leaves));
+            for index in 0..count {
+                let proof = inclusion_proof(&leaves, index).unwrap();
+                assert!(verify_proof(&root, &proof), "leaf {} of {}", index, count);
+            }
+            assert!(inclusion_proof(&leaves, count).is_none());
+        }
+    }
+
+    #[test]
+    fn test_tampered_proof_is_rejected() {
+        let leaves = leaves(5);
+        let root = hex::encode(merkle_root(&leaves));
+
+        let mut proof = inclusion_proof(&leaves, 2).unwrap();
+        proof.leaf = hex::encode(hash_leaf(b"forged"));
+        assert!(!verify_proof(&root, &proof));
+
+        let mut proof = inclusion_proof(&leaves, 2).unwrap();
+        proof.siblings[0].side = Side::Left;
+        assert!(!verify_proof(&root, &proof));
+    }
+}
@@ -1,38 +1,125 @@
 use crate::block::{Block, Transaction};
-use crate::blockchain::Blockchain;
+use crate::blockchain::{Blockchain, ImportOutcome};
 use crate::config::P2pConfig;
+use crate::errors::NodeError;
+use crate::consensus::Vote;
 use crate::mempool::Mempool;
+use crate::snapshot::{Snapshot, SnapshotDownload, SNAPSHOT_INTERVAL};
+use crate::sync::{self, SyncRequest, SyncResponse, SyncState, SYNC_PROTOCOL};
 
+use futures_util::StreamExt;
 use libp2p::{
-    identity, noise, yamux, PeerId, Swarm, tcp,
+    allow_block_list, noise, yamux, PeerId, Swarm, tcp,
     gossipsub::{self, IdentTopic as Topic, MessageAuthenticity},
     mdns,
-    swarm::{SwarmBuilder, SwarmEvent},
+    request_response::{self, ProtocolSupport},
+    swarm::{NetworkBehaviour, SwarmEvent},
 };
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::sync::mpsc;
 
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ChainMessage {
+    /// A finalized block carrying a quorum commit.
     Block(Block),
     Transaction(Transaction),
+    /// A block proposed for the next height, awaiting validator votes.
+    Proposal(Block),
+    Vote(Vote),
 }
 
-pub const CHAIN_TOPIC: Topic = Topic::new("kosher-chain-blocks");
-pub const TRANSACTION_TOPIC: Topic = Topic::new("kosher-chain-transactions");
+pub const CHAIN_TOPIC: &str = "kosher-chain-blocks";
+pub const TRANSACTION_TOPIC: &str = "kosher-chain-transactions";
+pub const CONSENSUS_TOPIC: &str = "kosher-chain-consensus";
 
-#[derive(libp2p::NetworkBehaviour)]
-#[behaviour(to_swarm = "ChainBehaviourEvent")]
+/// How often connected peers are asked for their chain status, so missed blocks are noticed.
+const STATUS_INTERVAL_SECONDS: u64 = 30;
+
+#[derive(NetworkBehaviour)]
 pub struct ChainBehaviour {
     pub gossipsub: gossipsub::Behaviour,
     pub mdns: mdns::tokio::Behaviour,
+    pub sync: request_response::json::Behaviour<SyncRequest, SyncResponse>,
+    /// Refuses connections from banned peers.
+    pub banned: allow_block_list::Behaviour<allow_block_list::BlockedPeers>,
+}
+
+/// Peers whose score falls below this are banned.
+const BAN_THRESHOLD: i32 = -100;
+
+#[derive(Debug, Default)]
+pub struct PeerInfo {
+    pub score: i32,
+}
+
+/// Tracks a reputation score per connected peer.
+#[derive(Debug, Default)]
+pub struct PeerManager {
+    peers: HashMap<PeerId, PeerInfo>,
+}
+
+impl PeerManager {
+    pub fn add_peer(&mut self, peer_id: PeerId) {
+        self.peers.entry(peer_id).or_default();
+    }
+    
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.peers.remove(peer_id);
+    }
+    
+    // Apply a positive score adjustment.
+    pub fn reward_peer(&mut self, peer_id: &PeerId, points: i32) {
+        if let Some(peer) = self.peers.get_mut(peer_id) {
+            peer.score += points;
+            println!("[PeerManager] Rewarded peer {}. New score: {}", peer_id, peer.score);
+        }
+    }
+    
+    // Apply a negative score adjustment and check if the peer should be banned.
+    pub fn penalize_peer(&mut self, peer_id: &PeerId, points: i32) -> bool {
+        if let Some(peer) = self.peers.get_mut(peer_id) {
+            peer.score -= points;
+            println!("[PeerManager] Penalized peer {}. New score: {}", peer_id, peer.score);
+            if peer.score < BAN_THRESHOLD {
+                println!("[PeerManager] 🚨 Peer {} has crossed the ban threshold!", peer_id);
+                return true; // Indicates the peer should be banned
+            }
+        }
+        false
+    }
 }
-//... Event wrappers from previous phases ...
 
-// Peer Management structs (PeerInfo, PeerManager) remain the same
+/// Builds a swarm with a fresh identity, speaking noise-encrypted yamux over TCP and
+/// subscribed to the chain's gossip topics.
+fn build_swarm() -> Result<Swarm<ChainBehaviour>, NodeError> {
+    let swarm = libp2p::SwarmBuilder::with_new_identity()
+        .with_tokio()
+        .with_tcp(tcp::Config::default(), noise::Config::new, yamux::Config::default)
+        .map_err(|e| NodeError::P2p(e.to_string()))?
+        .with_behaviour(|key| {
+            let mut gossipsub = gossipsub::Behaviour::new(
+                MessageAuthenticity::Signed(key.clone()),
+                gossipsub::Config::default(),
+            )?;
+            for topic in [CHAIN_TOPIC, TRANSACTION_TOPIC, CONSENSUS_TOPIC] {
+                gossipsub.subscribe(&Topic::new(topic))?;
+            }
+            let mdns = mdns::tokio::Behaviour::new(mdns::Config::default(), key.public().to_peer_id())?;
+            let sync = request_response::json::Behaviour::new(
+                [(SYNC_PROTOCOL, ProtocolSupport::Full)],
+                request_response::Config::default(),
+            );
+            Ok(ChainBehaviour { gossipsub, mdns, sync, banned: Default::default() })
+        })
+        .map_err(|e| NodeError::P2p(e.to_string()))?
+        .with_swarm_config(|config| config.with_idle_connection_timeout(Duration::from_secs(60)))
+        .build();
+    Ok(swarm)
+}
 
 pub async fn run_p2p_network(
     config: P2pConfig,
@@ -40,37 +127,76 @@ pub async fn run_p2p_network(
     mempool: Arc<Mutex<Mempool>>,
     peer_manager: Arc<Mutex<PeerManager>>,
     mut p2p_rx: mpsc::Receiver<ChainMessage>,
+    consensus_tx: Option<mpsc::Sender<ChainMessage>>,
 ) {
-    let id_keys = identity::Keypair::generate_ed25519();
-    let peer_id = PeerId::from(id_keys.public());
-    println!("[P2P] Local peer ID: {}", peer_id);
-
-    let transport = tcp::tokio::Transport::new(tcp::Config::default())
-        .upgrade(libp2p::core::upgrade::Version::V1Lazy)
-        .authenticate(noise::Config::new(&id_keys).unwrap())
-        .multiplex(yamux::Config::default())
-        .boxed();
-
-    let gossipsub_config = gossipsub::Config::default();
-    let mut gossipsub = gossipsub::Behaviour::new(
-        MessageAuthenticity::Signed(id_keys),
-        gossipsub_config,
-    ).unwrap();
-    gossipsub.subscribe(&CHAIN_TOPIC).unwrap();
-    gossipsub.subscribe(&TRANSACTION_TOPIC).unwrap();
-
-    let mdns = mdns::tokio::Behaviour::new(mdns::Config::default(), peer_id).unwrap();
-    let behaviour = ChainBehaviour { gossipsub, mdns };
-    let mut swarm = SwarmBuilder::with_tokio_executor(transport, behaviour, peer_id).build();
-    swarm.listen_on(config.listen_address.parse().unwrap()).unwrap();
+    let mut swarm = match build_swarm() {
+        Ok(swarm) => swarm,
+        Err(e) => {
+            eprintln!("[P2P] Failed to build the network swarm: {}", e);
+            return;
+        }
+    };
+    println!("[P2P] Local peer ID: {}", swarm.local_peer_id());
+    let listen_address = match config.listen_address.parse() {
+        Ok(address) => address,
+        Err(e) => {
+            eprintln!("[P2P] Invalid listen address {}: {}", config.listen_address, e);
+            return;
+        }
+    };
+    if let Err(e) = swarm.listen_on(listen_address) {
+        eprintln!("[P2P] Failed to listen on {}: {}", config.listen_address, e);
+        return;
+    }
+
+    let mut sync_state = SyncState::default();
+    // Proposals and votes are only forwarded to a local validator, so its presence marks a validator node.
+    let produces_snapshots = consensus_tx.is_some();
+    // Snapshots are serialized on a blocking thread and handed back to this loop when done.
+    let (snapshot_tx, mut snapshot_rx) = mpsc::channel(1);
+    let mut capturing_snapshot = false;
+    let mut status_interval = tokio::time::interval(Duration::from_secs(STATUS_INTERVAL_SECONDS));
 
     loop {
         tokio::select! {
+            _ = status_interval.tick() => {
+                if produces_snapshots && !capturing_snapshot {
+                    let due = {
+                        let chain = blockchain.lock().unwrap();
+                        let last_snapshot = sync_state.snapshot.as_ref().map_or(0, Snapshot::height);
+                        (chain.height() >= last_snapshot + SNAPSHOT_INTERVAL).then(|| {
+                            (chain.blocks.last().expect("Chain always contains a block").clone(), chain.state.clone())
+                        })
+                    };
+                    if let Some((head, state)) = due {
+                        capturing_snapshot = true;
+                        let snapshot_tx = snapshot_tx.clone();
+                        tokio::task::spawn_blocking(move || {
+                            let _ = snapshot_tx.blocking_send(Snapshot::from_state(head, state));
+                        });
+                    }
+                }
+                let peers: Vec<PeerId> = swarm.connected_peers().copied().collect();
+                for peer in peers {
+                    swarm.behaviour_mut().sync.send_request(&peer, SyncRequest::Status);
+                }
+            }
+            Some(captured) = snapshot_rx.recv() => {
+                capturing_snapshot = false;
+                match captured {
+                    Ok(snapshot) => {
+                        println!("[Sync] Captured state snapshot at height {}.", snapshot.height());
+                        sync_state.snapshot = Some(snapshot);
+                    }
+                    Err(e) => eprintln!("[Sync] Failed to capture state snapshot: {}", e),
+                }
+            }
             Some(msg_to_gossip) = p2p_rx.recv() => {
-                let topic = match &msg_to_gossip {
-                    ChainMessage::Block(_) => CHAIN_TOPIC.clone(),
-                    ChainMessage::Transaction(_) => TRANSACTION_TOPIC.clone(),
-                };
+                let topic = Topic::new(match &msg_to_gossip {
+                    ChainMessage::Block(_) => CHAIN_TOPIC,
+                    ChainMessage::Transaction(_) => TRANSACTION_TOPIC,
+                    ChainMessage::Proposal(_) | ChainMessage::Vote(_) => CONSENSUS_TOPIC,
+                });
                 if let Ok(json_msg) = serde_json::to_string(&msg_to_gossip) {
                     if let Err(e) = swarm.behaviour_mut().gossipsub.publish(topic, json_msg.as_bytes()) {
                         eprintln!("[P2P] Failed to publish message: {:?}", e);
@@ -84,19 +210,56 @@ pub async fn run_p2p_network(
                 SwarmEvent::ConnectionEstablished { peer_id, .. } => {
                     println!("[P2P] Connection established with: {}", peer_id);
                     peer_manager.lock().unwrap().add_peer(peer_id);
+                    swarm.behaviour_mut().sync.send_request(&peer_id, SyncRequest::Status);
                 }
                 SwarmEvent::ConnectionClosed { peer_id, .. } => {
                     println!("[P2P] Connection closed with: {}", peer_id);
                     peer_manager.lock().unwrap().remove_peer(&peer_id);
+                    sync_state.peer_heights.remove(&peer_id);
+                    if sync_state.download.as_ref().is_some_and(|(source, _)| *source == peer_id) {
+                        sync_state.download = None;
+                    }
+                }
+                SwarmEvent::Behaviour(ChainBehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
+                    for (peer, address) in peers {
+                        swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer);
+                        if let Err(e) = swarm.dial(address) {
+                            eprintln!("[P2P] Failed to dial discovered peer {}: {}", peer, e);
+                        }
+                    }
+                }
+                SwarmEvent::Behaviour(ChainBehaviourEvent::Mdns(mdns::Event::Expired(peers))) => {
+                    for (peer, _) in peers {
+                        swarm.behaviour_mut().gossipsub.remove_explicit_peer(&peer);
+                    }
                 }
                 SwarmEvent::Behaviour(ChainBehaviourEvent::Gossipsub(
-                    gossipsub::Event::Message { message, .. }
+                    gossipsub::Event::Message { propagation_source, message, .. }
                 )) => {
-                    let source_peer = message.source.unwrap();
+                    // The relaying peer is the one accountable for what it forwards to us.
+                    let source_peer = propagation_source;
                     if let Ok(msg) = serde_json::from_slice::<ChainMessage>(&message.data) {
-                        handle_gossip_message(msg, &blockchain, &mempool, &peer_manager, &source_peer, &mut swarm);
+                        handle_gossip_message(msg, &blockchain, &mempool, &peer_manager, &source_peer, &mut swarm, consensus_tx.as_ref());
                     }
                 }
+                SwarmEvent::Behaviour(ChainBehaviourEvent::Sync(
+                    request_response::Event::Message { peer, message }
+                )) => match message {
+                    request_response::Message::Request { request, channel, .. } => {
+                        let response = sync::answer(&blockchain.lock().unwrap(), sync_state.snapshot.as_ref(), request);
+                        if swarm.behaviour_mut().sync.send_response(channel, response).is_err() {
+                            eprintln!("[Sync] Failed to answer request from {}", peer);
+                        }
+                    }
+                    request_response::Message::Response { response, .. } => {
+                        handle_sync_response(response, &peer, &blockchain, &mempool, &peer_manager, &mut sync_state, &mut swarm);
+                    }
+                },
+                SwarmEvent::Behaviour(ChainBehaviourEvent::Sync(
+                    request_response::Event::OutboundFailure { peer, error, .. }
+                )) => {
+                    eprintln!("[Sync] Request to {} failed: {:?}", peer, error);
+                }
                 _ => {}
             }
         }
@@ -110,59 +273,218 @@ fn handle_gossip_message(
     peer_manager: &Arc<Mutex<PeerManager>>,
     source_peer: &PeerId,
     swarm: &mut Swarm<ChainBehaviour>,
+    consensus_tx: Option<&mpsc::Sender<ChainMessage>>,
 ) {
     match msg {
         ChainMessage::Block(block) => {
-            let mut chain = blockchain.lock().unwrap();
-            if chain.validate_and_add_block(block).is_ok() {
-                peer_manager.lock().unwrap().reward_peer(source_peer, 10);
-            } else {
-                if peer_manager.lock().unwrap().penalize_peer(source_peer, 50) {
-                    swarm.ban_peer(*source_peer);
+            // A block from further ahead means we have fallen behind; ask the peer where it is instead of rejecting it.
+            if block.header.id > blockchain.lock().unwrap().height() + 1 {
+                swarm.behaviour_mut().sync.send_request(source_peer, SyncRequest::Status);
+                return;
+            }
+            let outcome = blockchain.lock().unwrap().import_block(block);
+            match outcome {
+                Ok(outcome) => {
+                    match outcome {
+                        ImportOutcome::Extended => update_mempool(blockchain, mempool, Vec::new()),
+                        ImportOutcome::Reorganized { evicted } => update_mempool(blockchain, mempool, evicted),
+                        ImportOutcome::SideChain => {}
+                        // Gossip reaches us over several peers, so duplicates are expected and earn nothing.
+                        ImportOutcome::Known => return,
+                        // The peer is on a branch we haven't seen; syncing with it finds the common ancestor.
+                        ImportOutcome::Unconnected => {
+                            swarm.behaviour_mut().sync.send_request(source_peer, SyncRequest::Status);
+                            return;
+                        }
+                    }
+                    peer_manager.lock().unwrap().reward_peer(source_peer, 10);
+                }
+                Err(e) => {
+                    println!("[P2P] Rejected invalid block from {}: {}", source_peer, e);
+                    penalize(peer_manager, swarm, source_peer, 50);
                 }
             }
         }
         ChainMessage::Transaction(tx) => {
-            let mut mempool = mempool.lock().unwrap();
-            if mempool.add_transaction(tx).is_ok() {
-                 peer_manager.lock().unwrap().reward_peer(source_peer, 1);
-            } else {
-                if peer_manager.lock().unwrap().penalize_peer(source_peer, 5) {
-                    swarm.ban_peer(*source_peer);
+            let added = {
+                let chain = blockchain.lock().unwrap();
+                mempool.lock().unwrap().add_transaction(tx, &chain)
+            };
+            match added {
+                Ok(()) => peer_manager.lock().unwrap().reward_peer(source_peer, 1),
+                // A duplicate or a transaction our chain state rejects can be relayed in good faith.
+                Err(e) if !e.is_invalid() => {}
+                Err(e) => {
+                    println!("[P2P] Rejected invalid transaction from {}: {}", source_peer, e);
+                    penalize(peer_manager, swarm, source_peer, 5);
+                }
+            }
+        }
+        // Proposals and votes are only of interest to a local validator, which verifies them itself.
+        msg @ (ChainMessage::Proposal(_) | ChainMessage::Vote(_)) => {
+            if let Some(consensus_tx) = consensus_tx {
+                if consensus_tx.try_send(msg).is_err() {
+                    eprintln!("[P2P] Warning: consensus channel is full. Dropping message.");
                 }
             }
         }
     }
-}    peers: HashMap<PeerId, PeerInfo>,
 }
 
-impl PeerManager {
-    pub fn add_peer(&mut self, peer_id: PeerId) {
-        self.peers.entry(peer_id).or_default();
-    }
-    
-    pub fn remove_peer(&mut self, peer_id: &PeerId) {
-        self.peers.remove(peer_id);
-    }
-    
-    // Apply a positive score adjustment.
-    pub fn reward_peer(&mut self, peer_id: &PeerId, points: i32) {
-        if let Some(peer) = self.peers.get_mut(peer_id) {
-            peer.score += points;
-            println!("[PeerManager] Rewarded peer {}. New score: {}", peer_id, peer.score);
+/// Drives catching up with a peer: its status tells us whether we are behind, its headers
+/// where our chains diverge, and block ranges are then downloaded until we reach its height.
+/// A new node first restores the peer's latest state snapshot, if it has one, and only
+/// downloads the blocks after it.
+fn handle_sync_response(
+    response: SyncResponse,
+    peer: &PeerId,
+    blockchain: &Arc<Mutex<Blockchain>>,
+    mempool: &Arc<Mutex<Mempool>>,
+    peer_manager: &Arc<Mutex<PeerManager>>,
+    sync_state: &mut SyncState,
+    swarm: &mut Swarm<ChainBehaviour>,
+) {
+    let next_request = match response {
+        SyncResponse::Status { height, head_hash, snapshot_height } => {
+            sync_state.peer_heights.insert(*peer, height);
+            let (local_height, local_head) = {
+                let chain = blockchain.lock().unwrap();
+                (chain.height(), chain.head_hash())
+            };
+            if sync_state.download.is_some() {
+                // Block sync resumes once the snapshot is restored.
+                None
+            } else if sync::should_fetch_snapshot(local_height, snapshot_height) {
+                println!("[Sync] Peer {} offers a state snapshot at height {:?}. Fetching it.", peer, snapshot_height);
+                Some(SyncRequest::GetSnapshotManifest)
+            // An equal height with a different head can still be a heavier fork.
+            } else if height > local_height || (height == local_height && head_hash != local_head) {
+                println!("[Sync] Peer {} is at height {} (local {}). Syncing.", peer, height, local_height);
+                Some(sync::common_ancestor_request(local_height))
+            } else {
+                None
+            }
         }
-    }
-    
-    // Apply a negative score adjustment and check if the peer should be banned.
-    pub fn penalize_peer(&mut self, peer_id: &PeerId, points: i32) -> bool {
-        if let Some(peer) = self.peers.get_mut(peer_id) {
-            peer.score -= points;
-            println!("[PeerManager] Penalized peer {}. New score: {}", peer_id, peer.score);
-            if peer.score < BAN_THRESHOLD {
-                println!("[PeerManager] 🚨 Peer {} has crossed the ban threshold!", peer_id);
-                return true; // Indicates the peer should be banned
+        SyncResponse::Headers(headers) => {
+            let ancestor = sync::common_ancestor(&blockchain.lock().unwrap(), &headers);
+            sync::next_blocks_request(ancestor, sync_state.peer_heights.get(peer).copied().unwrap_or_default())
+        }
+        SyncResponse::Blocks(blocks) => {
+            let Some(last_height) = blocks.last().map(|block| block.header.id) else { return };
+            let mut evicted = Vec::new();
+            let mut failed = false;
+            {
+                let mut chain = blockchain.lock().unwrap();
+                for block in blocks {
+                    let height = block.header.id;
+                    match chain.import_block(block) {
+                        Ok(ImportOutcome::Reorganized { evicted: dropped }) => evicted.extend(dropped),
+                        // The peer was asked for blocks after the common ancestor, so each must attach.
+                        Ok(ImportOutcome::Unconnected) => {
+                            eprintln!("[Sync] Block {} from {} does not connect to the chain", height, peer);
+                            failed = true;
+                            break;
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            eprintln!("[Sync] Rejected block from {}: {}", peer, e);
+                            failed = true;
+                            break;
+                        }
+                    }
+                }
             }
+            update_mempool(blockchain, mempool, evicted);
+
+            if failed {
+                penalize(peer_manager, swarm, peer, 50);
+                return;
+            }
+            sync::next_blocks_request(last_height, sync_state.peer_heights.get(peer).copied().unwrap_or_default())
         }
-        false
+        SyncResponse::SnapshotManifest(manifest) => {
+            let Some(manifest) = manifest else { return };
+            if sync_state.download.is_some() {
+                return;
+            }
+            let started = SnapshotDownload::start(&blockchain.lock().unwrap(), *manifest);
+            match started {
+                Ok(download) => {
+                    let request = download.next_chunk()
+                        .map(|index| SyncRequest::GetSnapshotChunk { height: download.height(), index });
+                    sync_state.download = Some((*peer, download));
+                    request
+                }
+                Err(e) => {
+                    eprintln!("[Sync] Rejected snapshot manifest from {}: {}", peer, e);
+                    penalize(peer_manager, swarm, peer, 50);
+                    return;
+                }
+            }
+        }
+        SyncResponse::SnapshotChunk { height, index, data } => {
+            let Some((source, download)) = sync_state.download.as_mut() else { return };
+            if source != peer || download.height() != height {
+                return;
+            }
+            // The peer has moved on to a newer snapshot; start over on the next status round.
+            let Some(data) = data.and_then(|data| hex::decode(data).ok()) else {
+                sync_state.download = None;
+                return;
+            };
+            if let Err(e) = download.add_chunk(index, data) {
+                eprintln!("[Sync] Rejected snapshot chunk from {}: {}", peer, e);
+                sync_state.download = None;
+                penalize(peer_manager, swarm, peer, 50);
+                return;
+            }
+
+            match download.next_chunk() {
+                Some(index) => Some(SyncRequest::GetSnapshotChunk { height, index }),
+                None => {
+                    let (_, download) = sync_state.download.take().expect("Download checked above");
+                    let mut chain = blockchain.lock().unwrap();
+                    match download.finish(chain.chain_id, chain.validator_set.clone()).and_then(|restored| chain.replace_with(restored)) {
+                        Ok(()) => {
+                            println!("[Sync] Restored state snapshot at height {}.", height);
+                            mempool.lock().unwrap().revalidate(&chain);
+                            // Fetch the blocks produced since the snapshot.
+                            Some(SyncRequest::Status)
+                        }
+                        Err(e) => {
+                            drop(chain);
+                            eprintln!("[Sync] Rejected state snapshot from {}: {}", peer, e);
+                            penalize(peer_manager, swarm, peer, 50);
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    };
+
+    if let Some(request) = next_request {
+        swarm.behaviour_mut().sync.send_request(peer, request);
+    }
+}
+
+fn penalize(peer_manager: &Arc<Mutex<PeerManager>>, swarm: &mut Swarm<ChainBehaviour>, peer: &PeerId, points: i32) {
+    if peer_manager.lock().unwrap().penalize_peer(peer, points) {
+        swarm.behaviour_mut().banned.block_peer(*peer);
+    }
+}
+
+/// Brings the mempool up to date after the canonical chain changed, returning the
+/// transactions of blocks a reorg dropped to it.
+fn update_mempool(blockchain: &Arc<Mutex<Blockchain>>, mempool: &Arc<Mutex<Mempool>>, evicted: Vec<Transaction>) {
+    let chain = blockchain.lock().unwrap();
+    let mut mempool = mempool.lock().unwrap();
+    mempool.revalidate(&chain);
+    if evicted.is_empty() {
+        return;
+    }
+    println!("[P2P] Chain reorganized. Returning {} transactions to the mempool.", evicted.len());
+    for tx in evicted {
+        let _ = mempool.add_transaction(tx, &chain);
     }
 }
@@ -1,48 +1,318 @@
-use crate::blockchain::{Blockchain, AccountState};
+use crate::block::{Block, Receipt};
+use crate::blockchain::{Blockchain, AccountState, compute_state_root};
 use crate::errors::NodeError;
-use std::collections::{HashMap, HashSet};
-use std::fs;
-use std::path::Path;
+use crate::genesis::Genesis;
+use crate::storage::ChainStore;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 
 const STATE_FILE: &str = "blockchain_state.json";
+const BLOCK_LOG_FILE: &str = "blockchain_blocks.log";
+
+/// The state file starts with this prefix and the SHA-256 of the JSON that follows on the next line.
+const CHECKSUM_PREFIX: &str = "sha256:";
 
 /// The data structure that is saved to disk, containing all necessary state.
+/// State snapshots use the same structure, holding only the block they were taken at.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct PersistentState {
+    pub(crate) blocks: Vec<crate::block::Block>,
+    pub(crate) state: HashMap<String, AccountState>,
+    #[serde(default)]
+    pub(crate) receipts: HashMap<String, Receipt>,
+}
+
+/// A record in the block log. Each entry only applies if it builds on the chain's
+/// current head, so replaying entries the state file already contains is harmless.
 #[derive(serde::Serialize, serde::Deserialize)]
-struct PersistentState {
-    blocks: Vec<crate::block::Block>,
-    state: HashMap<String, AccountState>,
+enum LogEntry {
+    /// A block was added to the chain.
+    Commit(Block),
+    /// The head block was removed in a reorg, restoring the listed accounts.
+    Revert { block_hash: String, restored: HashMap<String, Option<AccountState>> },
 }
 
-/// Saves the current state of the blockchain to a file.
-pub fn save_state(chain: &Blockchain) -> Result<(), NodeError> {
+/// Saves the current state of the blockchain to a file in `data_dir` and clears the block log.
+/// The file is replaced atomically, so a crash leaves either the old or the new copy.
+pub fn save_state(chain: &Blockchain, data_dir: &Path) -> Result<(), NodeError> {
     println!("[Persistence] Saving blockchain state to disk...");
+    write_state_file(chain, &data_dir.join(STATE_FILE))?;
+    // Everything in the block log is now part of the state file.
+    let log_path = data_dir.join(BLOCK_LOG_FILE);
+    if log_path.exists() {
+        File::create(log_path)?.sync_all()?;
+    }
+    println!("[Persistence] State saved successfully. Total blocks: {}", chain.blocks.len());
+    Ok(())
+}
+
+/// Loads the blockchain state from a file in `data_dir`, or starts a new chain from
+/// `genesis` if it doesn't exist, then replays the block log on top of it.
+pub fn load_or_initialize_state(genesis: &Genesis, data_dir: &Path) -> Result<Blockchain, NodeError> {
+    let path = data_dir.join(STATE_FILE);
+    let mut chain = if path.exists() {
+        println!("[Persistence] Found existing state file. Loading from disk...");
+        let loaded_state = read_state_file(&path)?;
+
+        let chain = Blockchain::from_parts(
+            genesis.chain_id, genesis.validator_set(), loaded_state.blocks, loaded_state.state, loaded_state.receipts,
+        );
+
+        genesis.check(&chain)?;
+
+        println!("[Persistence] State loaded successfully. Current block height: {}", chain.height());
+        chain
+    } else {
+        println!("[Persistence] No state file found. Initializing a new blockchain.");
+        Blockchain::from_genesis(genesis)
+    };
+
+    replay_block_log(&mut chain, &data_dir.join(BLOCK_LOG_FILE))?;
+    Ok(chain)
+}
+
+/// Whether `data_dir` already holds a state file or a block log.
+pub fn state_exists(data_dir: &Path) -> bool {
+    data_dir.join(STATE_FILE).exists() || data_dir.join(BLOCK_LOG_FILE).exists()
+}
+
+/// Loads the chain and appends every block accepted from now on to the block log.
+pub fn open_chain(genesis: &Genesis, data_dir: &Path) -> Result<Blockchain, NodeError> {
+    let mut chain = load_or_initialize_state(genesis, data_dir)?;
+    chain.set_storage(Box::new(BlockLog { data_dir: data_dir.to_path_buf() }));
+    Ok(chain)
+}
+
+/// Writes the whole chain to `path` in the state file format, e.g. to seed another node.
+pub fn export_chain(chain: &Blockchain, path: &Path) -> Result<(), NodeError> {
+    write_state_file(chain, path)
+}
+
+/// Reads a chain written by `export_chain` and rebuilds it by re-executing every block
+/// on top of `genesis`, so nothing in the file is taken on trust.
+pub fn import_chain(path: &Path, genesis: &Genesis) -> Result<Blockchain, NodeError> {
+    let exported = read_state_file(path)?;
+    let mut chain = Blockchain::from_genesis(genesis);
+    let mut blocks = exported.blocks.into_iter();
+    match blocks.next() {
+        Some(first) if first.calculate_header_hash() == chain.head_hash() => {}
+        _ => return Err(NodeError::Blockchain("Exported chain does not start at this network's genesis block".into())),
+    }
+
+    for block in blocks {
+        chain.validate_and_add_block(block)?;
+    }
+
+    let accounts = exported.state.iter().map(|(key, account)| (key.as_str(), account)).collect();
+    if compute_state_root(&accounts) != chain.state_root() {
+        return Err(NodeError::Blockchain("Exported state does not match the state its blocks produce".into()));
+    }
+    Ok(chain)
+}
+
+fn write_state_file(chain: &Blockchain, path: &Path) -> Result<(), NodeError> {
     let state_to_save = PersistentState {
         blocks: chain.blocks.clone(),
         state: chain.state.clone(),
+        receipts: chain.receipts.clone(),
     };
 
     let data = serde_json::to_string_pretty(&state_to_save)?;
-    fs::write(STATE_FILE, data)?;
-    println!("[Persistence] State saved successfully. Total blocks: {}", chain.blocks.len());
+    let contents = format!("{}{}\n{}", CHECKSUM_PREFIX, hex::encode(Sha256::digest(&data)), data);
+    write_atomically(path, contents.as_bytes())?;
     Ok(())
 }
 
-/// Loads the blockchain state from a file, or creates a new one if it doesn't exist.
-pub fn load_or_initialize_state(validators: HashSet<String>) -> Result<Blockchain, NodeError> {
-    let path = Path::new(STATE_FILE);
-    if path.exists() {
-        println!("[Persistence] Found existing state file. Loading from disk...");
-        let data = fs::read_to_string(path)?;
-        let loaded_state: PersistentState = serde_json::from_str(&data)?;
-        
-        let mut chain = Blockchain::new(validators);
-        chain.blocks = loaded_state.blocks;
-        chain.state = loaded_state.state;
-        
-        println!("[Persistence] State loaded successfully. Current block height: {}", chain.blocks.len() - 1);
-        Ok(chain)
-    } else {
-        println!("[Persistence] No state file found. Initializing a new blockchain.");
-        Ok(Blockchain::new(validators))
+fn read_state_file(path: &Path) -> Result<PersistentState, NodeError> {
+    let contents = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(verified_payload(&contents)?)?)
+}
+
+/// Checks the state file's checksum and returns the JSON it covers.
+/// Files written before checksums were added are accepted as they are.
+fn verified_payload(contents: &str) -> Result<&str, NodeError> {
+    let Some(rest) = contents.strip_prefix(CHECKSUM_PREFIX) else {
+        println!("[Persistence] Warning: state file has no checksum. It will get one on the next save.");
+        return Ok(contents);
+    };
+    let (checksum, payload) = rest.split_once('\n').ok_or_else(|| corrupted("state file is truncated"))?;
+    if hex::encode(Sha256::digest(payload)) != checksum {
+        return Err(corrupted("state file checksum mismatch"));
+    }
+    Ok(payload)
+}
+
+/// Appends every change to the canonical chain to `BLOCK_LOG_FILE`. The log is
+/// replayed at startup and cleared whenever the full state file is rewritten.
+#[derive(Debug)]
+pub struct BlockLog {
+    data_dir: PathBuf,
+}
+
+impl BlockLog {
+    fn append(&self, entry: &LogEntry) -> Result<(), NodeError> {
+        let line = serde_json::to_string(entry)?;
+        let record = format!("{} {}\n", hex::encode(Sha256::digest(&line)), line);
+        let mut file = OpenOptions::new().create(true).append(true).open(self.data_dir.join(BLOCK_LOG_FILE))?;
+        file.write_all(record.as_bytes())?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+impl ChainStore for BlockLog {
+    fn commit_block(
+        &self,
+        block: &Block,
+        _accounts: &HashMap<String, Option<AccountState>>,
+        _undo: &HashMap<String, Option<AccountState>>,
+        _receipts: &[Receipt],
+    ) -> Result<(), NodeError> {
+        // Replaying re-executes the block, which rebuilds its undo data too, so the block alone is enough.
+        self.append(&LogEntry::Commit(block.clone()))
+    }
+
+    fn revert_block(&self, block: &Block, restored: &HashMap<String, Option<AccountState>>) -> Result<(), NodeError> {
+        self.append(&LogEntry::Revert { block_hash: block.calculate_header_hash(), restored: restored.clone() })
+    }
+
+    fn reset(&self, chain: &Blockchain) -> Result<(), NodeError> {
+        save_state(chain, &self.data_dir)
+    }
+}
+
+fn replay_block_log(chain: &mut Blockchain, path: &Path) -> Result<(), NodeError> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let contents = fs::read_to_string(path)?;
+    let lines: Vec<&str> = contents.lines().collect();
+    let mut replayed = 0;
+    for (number, line) in lines.iter().enumerate() {
+        let entry = line.split_once(' ')
+            .filter(|(checksum, payload)| hex::encode(Sha256::digest(payload)) == *checksum)
+            .and_then(|(_, payload)| serde_json::from_str::<LogEntry>(payload).ok());
+        let Some(entry) = entry else {
+            // A crash can only tear the record being appended, which is always the last one.
+            if number + 1 == lines.len() && !contents.ends_with('\n') {
+                println!("[Persistence] Warning: discarding incomplete last record in the block log.");
+                let valid_len = contents.rfind('\n').map_or(0, |end| end + 1);
+                OpenOptions::new().write(true).open(path)?.set_len(valid_len as u64)?;
+                break;
+            }
+            return Err(corrupted(&format!("block log record {} is corrupted", number + 1)));
+        };
+
+        match entry {
+            LogEntry::Commit(block) if block.header.previous_hash == chain.head_hash() => {
+                chain.validate_and_add_block(block)
+                    .map_err(|e| corrupted(&format!("block log record {} does not apply: {}", number + 1, e)))?;
+                replayed += 1;
+            }
+            LogEntry::Revert { block_hash, restored } if block_hash == chain.head_hash() => {
+                chain.revert_head(restored);
+                replayed += 1;
+            }
+            // Already contained in the state file.
+            _ => {}
+        }
+    }
+
+    println!("[Persistence] Replayed {} records from the block log. Current block height: {}", replayed, chain.height());
+    Ok(())
+}
+
+/// Writes to a temporary file, syncs it and renames it over `path`.
+pub(crate) fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut temp_name = path.as_os_str().to_owned();
+    temp_name.push(".tmp");
+    let temp_path = Path::new(&temp_name);
+
+    let mut file = File::create(temp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(temp_path, path)?;
+
+    // Make the rename itself durable.
+    #[cfg(unix)]
+    {
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+pub(crate) fn corrupted(reason: &str) -> NodeError {
+    NodeError::Persistence(io::Error::new(io::ErrorKind::InvalidData, reason.to_string()))
+}
+
+// --- Unit Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::tests::{next_block, transfer};
+    use crate::genesis::GenesisAccount;
+    use crate::wallet::Wallet;
+    use ethers_core::types::U256;
+
+    /// Opens a chain whose blocks are only in the block log, never in a state file.
+    fn chain_in_log(data_dir: &Path) -> (Genesis, Blockchain) {
+        let (validator, user) = (Wallet::new(), Wallet::new());
+        let mut genesis = Genesis::with_validators([validator.public_key_hex()].into_iter().collect());
+        genesis.alloc.insert(user.address(), GenesisAccount { balance: U256::from(10_000_000), ..Default::default() });
+
+        let mut chain = open_chain(&genesis, data_dir).unwrap();
+        for nonce in 0..2 {
+            let block = next_block(&chain, &validator, vec![transfer(&user, nonce)]);
+            chain.validate_and_add_block(block).unwrap();
+        }
+        assert!(!data_dir.join(STATE_FILE).exists());
+        (genesis, chain)
+    }
+
+    #[test]
+    fn test_block_log_is_replayed_on_restart() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let (genesis, chain) = chain_in_log(dir.path());
+
+        let reopened = load_or_initialize_state(&genesis, dir.path()).unwrap();
+        assert_eq!(reopened.height(), 2);
+        assert_eq!(reopened.state_root(), chain.state_root());
+        assert_eq!(reopened.head_hash(), chain.head_hash());
+    }
+
+    #[test]
+    fn test_torn_last_log_record_is_ignored() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let (genesis, chain) = chain_in_log(dir.path());
+        let log_path = dir.path().join(BLOCK_LOG_FILE);
+        let intact_len = fs::metadata(&log_path).unwrap().len();
+
+        // A crash in the middle of appending the next record.
+        let mut file = OpenOptions::new().append(true).open(&log_path).unwrap();
+        file.write_all(b"0badc0de {\"Commit\":{\"hea").unwrap();
+        drop(file);
+
+        let reopened = load_or_initialize_state(&genesis, dir.path()).unwrap();
+        assert_eq!(reopened.height(), 2);
+        assert_eq!(reopened.state_root(), chain.state_root());
+        assert_eq!(fs::metadata(&log_path).unwrap().len(), intact_len);
+    }
+
+    #[test]
+    fn test_state_file_checksum_detects_corruption() {
+        let data = r#"{"blocks":[],"state":{}}"#;
+        let contents = format!("{}{}\n{}", CHECKSUM_PREFIX, hex::encode(Sha256::digest(data)), data);
+        assert_eq!(verified_payload(&contents).unwrap(), data);
+
+        let corrupted = contents.replace("blocks", "blockz");
+        match verified_payload(&corrupted) {
+            Err(NodeError::Persistence(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+            other => panic!("unexpected result: {:?}", other.map(str::len)),
+        }
     }
 }
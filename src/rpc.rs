@@ -0,0 +1,504 @@
+use crate::api::AppState;
+use crate::block::{Block, Log, Receipt, Transaction, TransactionAction, decode_ethereum_transaction};
+use crate::blockchain::{Blockchain, account_key, BLOCK_GAS_LIMIT};
+use crate::evm::{self, CallRequest};
+use crate::p2p::ChainMessage;
+
+use axum::{extract::State, Json};
+use ethers_core::types::{Address, Bytes, H256, U256};
+use revm::primitives::{Address as RevmAddress, ExecutionResult, U256 as RevmU256};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+// Standard JSON-RPC 2.0 error codes, plus the code Ethereum clients expect for reverts.
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+const EXECUTION_REVERTED: i64 = 3;
+
+/// The widest block range a single `eth_getLogs` request may scan while holding the chain lock.
+const MAX_LOG_BLOCK_RANGE: u64 = 1_000;
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Vec<Value>,
+}
+
+#[derive(Debug, Serialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self { code, message: message.into(), data: None }
+    }
+}
+
+/// The call object accepted by `eth_call` and `eth_estimateGas`.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct CallObject {
+    from: Option<Address>,
+    to: Option<Address>,
+    gas: Option<U256>,
+    value: Option<U256>,
+    data: Option<Bytes>,
+    input: Option<Bytes>,
+}
+
+/// The filter object accepted by `eth_getLogs`.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct LogFilter {
+    from_block: Option<String>,
+    to_block: Option<String>,
+    address: Option<Value>,
+    #[serde(default)]
+    topics: Vec<Value>,
+}
+
+/// Handles Ethereum JSON-RPC 2.0 requests, single or batched.
+/// Only the latest state is available, so block tags other than "latest" are ignored
+/// by the state-reading methods.
+pub async fn handle_rpc(State(state): State<AppState>, Json(body): Json<Value>) -> Json<Value> {
+    match body {
+        Value::Array(requests) => Json(Value::Array(
+            requests.into_iter().map(|request| handle_single(&state, request)).collect(),
+        )),
+        request => Json(handle_single(&state, request)),
+    }
+}
+
+fn handle_single(state: &AppState, request: Value) -> Value {
+    let request: RpcRequest = match serde_json::from_value(request) {
+        Ok(request) => request,
+        Err(e) => return error_response(Value::Null, RpcError::new(-32600, format!("Invalid request: {}", e))),
+    };
+
+    match dispatch(state, &request.method, &request.params) {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": request.id, "result": result }),
+        Err(error) => error_response(request.id, error),
+    }
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": error })
+}
+
+fn dispatch(state: &AppState, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+    match method {
+        "eth_chainId" => Ok(json!(format!("0x{:x}", state.blockchain.lock().unwrap().chain_id))),
+        "net_version" => Ok(json!(state.blockchain.lock().unwrap().chain_id.to_string())),
+        "eth_blockNumber" => {
+            let chain = state.blockchain.lock().unwrap();
+            Ok(json!(format!("0x{:x}", chain.height())))
+        }
+        "eth_getBalance" => {
+            let address: Address = param(params, 0)?;
+            let chain = state.blockchain.lock().unwrap();
+            let balance = chain.state.get(&account_key(&address.0)).map(|account| account.balance).unwrap_or_default();
+            Ok(json!(format!("{:#x}", balance)))
+        }
+        "eth_getTransactionCount" => {
+            let address: Address = param(params, 0)?;
+            let chain = state.blockchain.lock().unwrap();
+            let nonce = chain.state.get(&account_key(&address.0)).map(|account| account.nonce).unwrap_or_default();
+            Ok(json!(format!("0x{:x}", nonce)))
+        }
+        "eth_getCode" => {
+            let address: Address = param(params, 0)?;
+            let chain = state.blockchain.lock().unwrap();
+            let code = chain.state.get(&account_key(&address.0))
+                .and_then(|account| account.bytecode.as_ref())
+                .map(|code| code.original_bytes())
+                .unwrap_or_default();
+            Ok(json!(format!("0x{}", hex::encode(code))))
+        }
+        "eth_getStorageAt" => {
+            let address: Address = param(params, 0)?;
+            let slot: U256 = param(params, 1)?;
+            let chain = state.blockchain.lock().unwrap();
+            let value = chain.state.get(&account_key(&address.0))
+                .and_then(|account| account.storage.get(&RevmU256::from_limbs(slot.0)).copied())
+                .unwrap_or_default();
+            Ok(json!(format!("0x{}", hex::encode(value.to_be_bytes::<32>()))))
+        }
+        "eth_call" => {
+            let call: CallObject = param(params, 0)?;
+            let chain = state.blockchain.lock().unwrap();
+            match simulate(&chain, call)? {
+                ExecutionResult::Success { output, .. } => Ok(json!(format!("0x{}", hex::encode(output.into_data())))),
+                result => Err(execution_error(result)),
+            }
+        }
+        "eth_estimateGas" => {
+            let call: CallObject = param(params, 0)?;
+            let chain = state.blockchain.lock().unwrap();
+            match simulate(&chain, call)? {
+                ExecutionResult::Success { gas_used, .. } => Ok(json!(format!("0x{:x}", gas_used))),
+                result => Err(execution_error(result)),
+            }
+        }
+        "eth_sendRawTransaction" => {
+            let raw: Bytes = param(params, 0)?;
+            send_raw_transaction(state, raw.to_vec())
+        }
+        "eth_getBlockByNumber" => {
+            let tag: String = param(params, 0)?;
+            let full: bool = params.get(1).and_then(Value::as_bool).unwrap_or(false);
+            let chain = state.blockchain.lock().unwrap();
+            let height = parse_block_tag(&chain, &tag)?;
+            Ok(chain.block_by_height(height).map(|block| block_json(block, full)).unwrap_or(Value::Null))
+        }
+        "eth_getTransactionByHash" => {
+            let hash: String = param(params, 0)?;
+            let chain = state.blockchain.lock().unwrap();
+            Ok(chain.find_transaction(hash.trim_start_matches("0x"))
+                .map(|(block, tx)| transaction_json(block, tx))
+                .unwrap_or(Value::Null))
+        }
+        "eth_getTransactionReceipt" => {
+            let hash: String = param(params, 0)?;
+            let chain = state.blockchain.lock().unwrap();
+            Ok(chain.find_transaction(hash.trim_start_matches("0x"))
+                .and_then(|(block, tx)| chain.receipt(&tx.hash).map(|receipt| receipt_json(&chain, block, tx, receipt)))
+                .unwrap_or(Value::Null))
+        }
+        "eth_getLogs" => {
+            let filter: LogFilter = param(params, 0)?;
+            let chain = state.blockchain.lock().unwrap();
+            get_logs(&chain, filter)
+        }
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("Method {} not found", method))),
+    }
+}
+
+fn param<T: serde::de::DeserializeOwned>(params: &[Value], index: usize) -> Result<T, RpcError> {
+    let value = params.get(index).cloned().unwrap_or(Value::Null);
+    serde_json::from_value(value)
+        .map_err(|e| RpcError::new(INVALID_PARAMS, format!("Invalid parameter {}: {}", index, e)))
+}
+
+fn parse_block_tag(chain: &Blockchain, tag: &str) -> Result<u64, RpcError> {
+    match tag {
+        "latest" | "pending" | "safe" | "finalized" => Ok(chain.height()),
+        "earliest" => Ok(0),
+        hex_number => u64::from_str_radix(hex_number.trim_start_matches("0x"), 16)
+            .map_err(|_| RpcError::new(INVALID_PARAMS, format!("Invalid block tag {}", tag))),
+    }
+}
+
+fn simulate(chain: &Blockchain, call: CallObject) -> Result<ExecutionResult, RpcError> {
+    let gas_limit = call.gas
+        .map(|gas| u64::try_from(gas).map_err(|_| RpcError::new(INVALID_PARAMS, format!("Gas {} is out of range", gas))))
+        .transpose()?;
+    let request = CallRequest {
+        from: call.from.map(|addr| RevmAddress::from(addr.0)),
+        to: call.to.map(|addr| RevmAddress::from(addr.0)),
+        data: call.input.or(call.data).map(|data| data.to_vec().into()).unwrap_or_default(),
+        value: call.value.map(|value| RevmU256::from_limbs(value.0)).unwrap_or_default(),
+        gas_limit,
+    };
+    evm::simulate_call(chain, request).map_err(|e| RpcError::new(SERVER_ERROR, e.to_string()))
+}
+
+fn execution_error(result: ExecutionResult) -> RpcError {
+    match result {
+        ExecutionResult::Revert { output, .. } => RpcError {
+            code: EXECUTION_REVERTED,
+            message: "execution reverted".to_string(),
+            data: Some(json!(format!("0x{}", hex::encode(output)))),
+        },
+        ExecutionResult::Halt { reason, .. } => RpcError::new(SERVER_ERROR, format!("execution halted: {:?}", reason)),
+        ExecutionResult::Success { .. } => RpcError::new(SERVER_ERROR, "unexpected success"),
+    }
+}
+
+fn send_raw_transaction(state: &AppState, raw: Vec<u8>) -> Result<Value, RpcError> {
+    let chain = state.blockchain.lock().unwrap();
+    let tx = Transaction::from_ethereum_raw(raw, chain.chain_id).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+    state.mempool.lock().unwrap()
+        .add_local_transaction(tx.clone(), &chain)
+        .map_err(|e| RpcError::new(SERVER_ERROR, e.to_string()))?;
+    drop(chain);
+
+    println!("[RPC] Accepted new Ethereum transaction: {}", tx.hash);
+    let hash = format!("0x{}", tx.hash);
+    if state.p2p_tx.try_send(ChainMessage::Transaction(tx)).is_err() {
+        eprintln!("[RPC] Warning: P2P channel is full. Transaction not gossiped immediately.");
+    }
+    Ok(json!(hash))
+}
+
+/// Returns the recipient of a transaction, or `None` for contract creation.
+fn transaction_to(tx: &Transaction) -> Option<Address> {
+    match &tx.action {
+        TransactionAction::Transfer { recipient, .. } => Some(*recipient),
+        TransactionAction::Call { to, .. } => *to,
+        TransactionAction::Ethereum { raw } => decode_ethereum_transaction(raw, tx.chain_id).ok().and_then(|(typed, _)| typed.to_addr().copied()),
+    }
+}
+
+fn block_json(block: &Block, full: bool) -> Value {
+    let transactions: Vec<Value> = block.transactions.iter()
+        .map(|tx| if full { transaction_json(block, tx) } else { json!(format!("0x{}", tx.hash)) })
+        .collect();
+    json!({
+        "number": format!("0x{:x}", block.header.id),
+        "hash": format!("0x{}", block.calculate_header_hash()),
+        "parentHash": format!("0x{}", block.header.previous_hash),
+        "timestamp": format!("0x{:x}", block.header.timestamp),
+        "transactionsRoot": format!("0x{}", block.header.transactions_hash),
+        "stateRoot": format!("0x{}", block.header.state_root),
+        "receiptsRoot": format!("0x{}", block.header.receipts_root),
+        "gasLimit": format!("0x{:x}", BLOCK_GAS_LIMIT),
+        "transactions": transactions,
+    })
+}
+
+fn transaction_json(block: &Block, tx: &Transaction) -> Value {
+    let index = block.transactions.iter().position(|other| other == tx).unwrap_or_default();
+    json!({
+        "hash": format!("0x{}", tx.hash),
+        "from": tx.sender,
+        "to": transaction_to(tx),
+        "nonce": format!("0x{:x}", tx.nonce),
+        "gas": format!("0x{:x}", tx.gas_limit),
+        "gasPrice": format!("{:#x}", tx.max_fee_per_gas),
+        "blockNumber": format!("0x{:x}", block.header.id),
+        "blockHash": format!("0x{}", block.calculate_header_hash()),
+        "transactionIndex": format!("0x{:x}", index),
+    })
+}
+
+fn receipt_json(chain: &Blockchain, block: &Block, tx: &Transaction, receipt: &Receipt) -> Value {
+    let block_hash = block.calculate_header_hash();
+    let earlier_receipts: Vec<&Receipt> = block.transactions[..receipt.index].iter()
+        .filter_map(|earlier| chain.receipt(&earlier.hash))
+        .collect();
+    let cumulative_gas_used = earlier_receipts.iter().map(|earlier| earlier.gas_used).sum::<u64>() + receipt.gas_used;
+    let first_log_index = earlier_receipts.iter().map(|earlier| earlier.logs.len()).sum::<usize>();
+    let logs: Vec<Value> = receipt.logs.iter().enumerate()
+        .map(|(offset, log)| log_json(block, &block_hash, receipt, first_log_index + offset, log))
+        .collect();
+    json!({
+        "transactionHash": format!("0x{}", tx.hash),
+        "transactionIndex": format!("0x{:x}", receipt.index),
+        "blockNumber": format!("0x{:x}", block.header.id),
+        "blockHash": format!("0x{}", block_hash),
+        "from": tx.sender,
+        "to": transaction_to(tx),
+        "contractAddress": receipt.contract_address,
+        "gasUsed": format!("0x{:x}", receipt.gas_used),
+        "cumulativeGasUsed": format!("0x{:x}", cumulative_gas_used),
+        "effectiveGasPrice": format!("{:#x}", tx.max_fee_per_gas),
+        "logs": logs,
+        "status": if receipt.success { "0x1" } else { "0x0" },
+    })
+}
+
+fn log_json(block: &Block, block_hash: &str, receipt: &Receipt, log_index: usize, log: &Log) -> Value {
+    json!({
+        "address": log.address,
+        "topics": log.topics,
+        "data": format!("0x{}", hex::encode(&log.data)),
+        "blockNumber": format!("0x{:x}", block.header.id),
+        "blockHash": format!("0x{}", block_hash),
+        "transactionHash": format!("0x{}", receipt.tx_hash),
+        "transactionIndex": format!("0x{:x}", receipt.index),
+        "logIndex": format!("0x{:x}", log_index),
+        "removed": false,
+    })
+}
+
+/// Returns every log in the filter's block range that matches its address and topic filters.
+fn get_logs(chain: &Blockchain, filter: LogFilter) -> Result<Value, RpcError> {
+    let from = parse_block_tag(chain, filter.from_block.as_deref().unwrap_or("latest"))?;
+    let to = parse_block_tag(chain, filter.to_block.as_deref().unwrap_or("latest"))?.min(chain.height());
+    if to.saturating_sub(from) >= MAX_LOG_BLOCK_RANGE {
+        return Err(RpcError::new(INVALID_PARAMS, format!("Block range exceeds {} blocks", MAX_LOG_BLOCK_RANGE)));
+    }
+    let addresses: Vec<Address> = match filter.address {
+        Some(Value::Array(values)) => serde_json::from_value(Value::Array(values)),
+        Some(Value::Null) | None => Ok(Vec::new()),
+        Some(value) => serde_json::from_value(value).map(|address| vec![address]),
+    }.map_err(|e| RpcError::new(INVALID_PARAMS, format!("Invalid address filter: {}", e)))?;
+    // Each position is either a wildcard (null), a single topic, or a list of alternatives.
+    let topics: Vec<Vec<H256>> = filter.topics.into_iter()
+        .map(|topic| match topic {
+            Value::Null => Ok(Vec::new()),
+            Value::Array(values) => serde_json::from_value(Value::Array(values)),
+            value => serde_json::from_value(value).map(|topic| vec![topic]),
+        })
+        .collect::<Result<_, _>>()
+        .map_err(|e| RpcError::new(INVALID_PARAMS, format!("Invalid topic filter: {}", e)))?;
+
+    let mut matches = Vec::new();
+    for height in from..=to {
+        let Some(block) = chain.block_by_height(height) else { continue };
+        let block_hash = block.calculate_header_hash();
+        let mut log_index = 0;
+        for receipt in block.transactions.iter().filter_map(|tx| chain.receipt(&tx.hash)) {
+            for log in &receipt.logs {
+                let address_matches = addresses.is_empty() || addresses.contains(&log.address);
+                let topics_match = topics.iter().enumerate().all(|(position, alternatives)| {
+                    alternatives.is_empty() || log.topics.get(position).is_some_and(|topic| alternatives.contains(topic))
+                });
+                if address_matches && topics_match {
+                    matches.push(log_json(block, &block_hash, receipt, log_index, log));
+                }
+                log_index += 1;
+            }
+        }
+    }
+    Ok(Value::Array(matches))
+}
+
+// --- Unit Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::tests::sign_ethereum;
+    use crate::block::KOSHER_CHAIN_ID;
+    use crate::blockchain::tests::next_block;
+    use crate::genesis::{Genesis, GenesisAccount};
+    use crate::mempool::Mempool;
+    use crate::wallet::Wallet;
+    use ethers_core::types::TransactionRequest;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::mpsc;
+
+    /// Returns 42 as a 32-byte word.
+    const ANSWER_CODE: &[u8] = &[0x60, 0x2a, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3];
+    /// Emits an empty log with the single topic 7.
+    const LOGGER_CODE: &[u8] = &[0x60, 0x07, 0x60, 0x00, 0x60, 0x00, 0xa1, 0x00];
+
+    fn answer() -> Address {
+        Address::repeat_byte(0xa0)
+    }
+
+    fn logger() -> Address {
+        Address::repeat_byte(0xa1)
+    }
+
+    /// A single-validator chain with the test contracts deployed and `user` and `eth_signer` funded.
+    fn app_state(validator: &Wallet, user: &Wallet, eth_signer: Address) -> (AppState, mpsc::Receiver<ChainMessage>) {
+        let mut genesis = Genesis::with_validators([validator.public_key_hex()].into_iter().collect());
+        for funded in [user.address(), eth_signer] {
+            genesis.alloc.insert(funded, GenesisAccount { balance: U256::from(10_000_000), ..Default::default() });
+        }
+        for (address, code) in [(answer(), ANSWER_CODE), (logger(), LOGGER_CODE)] {
+            genesis.alloc.insert(address, GenesisAccount { code: Some(code.to_vec().into()), ..Default::default() });
+        }
+        let (p2p_tx, p2p_rx) = mpsc::channel(8);
+        let state = AppState {
+            blockchain: Arc::new(Mutex::new(Blockchain::from_genesis(&genesis))),
+            mempool: Arc::new(Mutex::new(Mempool::new())),
+            p2p_tx,
+        };
+        (state, p2p_rx)
+    }
+
+    fn error_code(result: Result<Value, RpcError>) -> i64 {
+        result.err().map(|error| error.code).unwrap_or_default()
+    }
+
+    #[test]
+    fn test_reads_chain_and_account_state() {
+        let (validator, user) = (Wallet::new(), Wallet::new());
+        let (state, _p2p_rx) = app_state(&validator, &user, Address::zero());
+
+        assert_eq!(dispatch(&state, "eth_chainId", &[]).unwrap(), json!(format!("0x{:x}", KOSHER_CHAIN_ID)));
+        assert_eq!(dispatch(&state, "eth_blockNumber", &[]).unwrap(), json!("0x0"));
+        let balance = dispatch(&state, "eth_getBalance", &[json!(user.address()), json!("latest")]).unwrap();
+        assert_eq!(balance, json!(format!("{:#x}", U256::from(10_000_000))));
+        let code = dispatch(&state, "eth_getCode", &[json!(answer()), json!("latest")]).unwrap();
+        assert_eq!(code, json!(format!("0x{}", hex::encode(ANSWER_CODE))));
+        assert_eq!(error_code(dispatch(&state, "eth_mine", &[])), METHOD_NOT_FOUND);
+    }
+
+    #[test]
+    fn test_call_returns_output_and_rejects_oversized_gas() {
+        let (validator, user) = (Wallet::new(), Wallet::new());
+        let (state, _p2p_rx) = app_state(&validator, &user, Address::zero());
+
+        let call = json!({ "from": user.address(), "to": answer() });
+        let output = dispatch(&state, "eth_call", &[call.clone(), json!("latest")]).unwrap();
+        assert_eq!(output, json!(format!("0x{:064x}", 42)));
+        let gas: String = serde_json::from_value(dispatch(&state, "eth_estimateGas", &[call]).unwrap()).unwrap();
+        assert!(u64::from_str_radix(gas.trim_start_matches("0x"), 16).unwrap() > 21_000);
+
+        let oversized = json!({ "to": answer(), "gas": "0x10000000000000000" });
+        assert_eq!(error_code(dispatch(&state, "eth_call", std::slice::from_ref(&oversized))), INVALID_PARAMS);
+        assert_eq!(error_code(dispatch(&state, "eth_estimateGas", &[oversized])), INVALID_PARAMS);
+    }
+
+    #[test]
+    fn test_send_raw_transaction_pools_and_gossips() {
+        let request = || TransactionRequest::new().to(Address::repeat_byte(0x22)).value(1_000).nonce(0).gas(21_000).gas_price(2);
+        let (raw, signer) = sign_ethereum(request(), Some(KOSHER_CHAIN_ID));
+        let (validator, user) = (Wallet::new(), Wallet::new());
+        let (state, mut p2p_rx) = app_state(&validator, &user, signer);
+
+        let hash = dispatch(&state, "eth_sendRawTransaction", &[json!(format!("0x{}", hex::encode(&raw)))]).unwrap();
+        let pooled = state.mempool.lock().unwrap().get_transactions(10);
+        assert_eq!(pooled.len(), 1);
+        assert_eq!((pooled[0].sender, json!(format!("0x{}", pooled[0].hash))), (signer, hash));
+        assert!(matches!(p2p_rx.try_recv(), Ok(ChainMessage::Transaction(tx)) if tx == pooled[0]));
+
+        let (other_chain, _) = sign_ethereum(request(), Some(1));
+        let result = dispatch(&state, "eth_sendRawTransaction", &[json!(format!("0x{}", hex::encode(other_chain)))]);
+        assert_eq!(error_code(result), INVALID_PARAMS);
+    }
+
+    #[test]
+    fn test_logs_and_receipts_of_included_transactions() {
+        let (validator, user) = (Wallet::new(), Wallet::new());
+        let (state, _p2p_rx) = app_state(&validator, &user, Address::zero());
+        let action = TransactionAction::Call { to: Some(logger()), data: vec![], value: U256::zero() };
+        let tx = Transaction::new_signed(&user, KOSHER_CHAIN_ID, action, 0, 100_000, U256::one());
+        {
+            let mut chain = state.blockchain.lock().unwrap();
+            let block = next_block(&chain, &validator, vec![tx.clone()]);
+            chain.validate_and_add_block(block).unwrap();
+        }
+
+        let receipt = dispatch(&state, "eth_getTransactionReceipt", &[json!(format!("0x{}", tx.hash))]).unwrap();
+        assert_eq!((&receipt["status"], receipt["logs"].as_array().unwrap().len()), (&json!("0x1"), 1));
+
+        let topic = H256::from_low_u64_be(7);
+        let filter = |topic: H256| json!({ "fromBlock": "0x0", "toBlock": "latest", "address": logger(), "topics": [topic] });
+        let logs = dispatch(&state, "eth_getLogs", &[filter(topic)]).unwrap();
+        assert_eq!(logs.as_array().unwrap().len(), 1);
+        assert_eq!(logs[0]["transactionHash"], json!(format!("0x{}", tx.hash)));
+        let logs = dispatch(&state, "eth_getLogs", &[filter(H256::from_low_u64_be(8))]).unwrap();
+        assert!(logs.as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_get_logs_rejects_wide_block_ranges() {
+        let (validator, user) = (Wallet::new(), Wallet::new());
+        let (state, _p2p_rx) = app_state(&validator, &user, Address::zero());
+        let mut chain = state.blockchain.lock().unwrap();
+        // Only the heights matter to the range check, so empty copies of genesis stand in for real blocks.
+        let genesis_block = chain.blocks[0].clone();
+        chain.blocks.extend((1..=MAX_LOG_BLOCK_RANGE).map(|height| {
+            let mut block = genesis_block.clone();
+            block.header.id = height;
+            block
+        }));
+
+        // Ranges are clamped to the chain height before the span is checked.
+        assert!(get_logs(&chain, LogFilter { from_block: Some("0x1".into()), to_block: Some("0xffffffff".into()), ..Default::default() }).is_ok());
+        let result = get_logs(&chain, LogFilter { from_block: Some("earliest".into()), ..Default::default() });
+        assert_eq!(error_code(result), INVALID_PARAMS);
+    }
+}
@@ -0,0 +1,259 @@
+use crate::block::Block;
+use crate::blockchain::{compute_state_root, AccountState, Blockchain};
+use crate::errors::NodeError;
+use crate::persistence::PersistentState;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Validators capture a new state snapshot every this many blocks.
+pub const SNAPSHOT_INTERVAL: u64 = 1_000;
+
+/// The size of a snapshot chunk in bytes.
+pub const CHUNK_SIZE: usize = 256 * 1024;
+
+/// The most chunks a snapshot manifest may list. Together with `CHUNK_SIZE` this caps a
+/// download at 4 GiB, so a peer can't make the node buffer an unbounded amount of data.
+pub const MAX_SNAPSHOT_CHUNKS: usize = 16 * 1024;
+
+/// Describes a snapshot: the finalized block it was taken at, whose header
+/// commits to the snapshot's state root, and the hash of every chunk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    pub block: Block,
+    pub chunk_hashes: Vec<String>,
+}
+
+impl SnapshotManifest {
+    pub fn height(&self) -> u64 {
+        self.block.header.id
+    }
+}
+
+/// The chain state at one height, serialized as a `PersistentState` and split into chunks.
+pub struct Snapshot {
+    pub manifest: SnapshotManifest,
+    chunks: Vec<Vec<u8>>,
+}
+
+impl Snapshot {
+    /// Serializes and chunks `state`, the state after `head`. This is the slow part of a
+    /// capture, so it can run on a copy of the state without holding the chain lock.
+    pub fn from_state(head: Block, state: HashMap<String, AccountState>) -> Result<Self, NodeError> {
+        let snapshot = PersistentState {
+            blocks: vec![head.clone()],
+            state,
+            receipts: HashMap::new(),
+        };
+
+        let data = serde_json::to_vec(&snapshot)?;
+        let chunks: Vec<Vec<u8>> = data.chunks(CHUNK_SIZE).map(<[u8]>::to_vec).collect();
+        let chunk_hashes = chunks.iter().map(|chunk| hex::encode(Sha256::digest(chunk))).collect();
+        Ok(Self {
+            manifest: SnapshotManifest { block: head, chunk_hashes },
+            chunks,
+        })
+    }
+
+    pub fn height(&self) -> u64 {
+        self.manifest.height()
+    }
+
+    pub fn chunk(&self, index: usize) -> Option<&[u8]> {
+        self.chunks.get(index).map(Vec::as_slice)
+    }
+}
+
+/// A snapshot being downloaded. Chunks are fetched in order and each is checked
+/// against the manifest as it arrives.
+pub struct SnapshotDownload {
+    manifest: SnapshotManifest,
+    chunks: Vec<Vec<u8>>,
+}
+
+impl SnapshotDownload {
+    /// Starts a download after checking that the manifest's block carries a valid quorum commit
+    /// and that the snapshot is no larger than `MAX_SNAPSHOT_CHUNKS` chunks.
+    pub fn start(chain: &Blockchain, manifest: SnapshotManifest) -> Result<Self, NodeError> {
+        if manifest.chunk_hashes.len() > MAX_SNAPSHOT_CHUNKS {
+            return Err(NodeError::Blockchain(format!(
+                "Snapshot manifest lists {} chunks, more than the maximum of {}", manifest.chunk_hashes.len(), MAX_SNAPSHOT_CHUNKS
+            )));
+        }
+        chain.verify_commit(&manifest.block)?;
+        if manifest.chunk_hashes.is_empty() {
+            return Err(NodeError::Blockchain("Snapshot manifest lists no chunks".into()));
+        }
+        Ok(Self { manifest, chunks: Vec::new() })
+    }
+
+    pub fn height(&self) -> u64 {
+        self.manifest.height()
+    }
+
+    /// The index of the next chunk to request, or `None` once all chunks have arrived.
+    pub fn next_chunk(&self) -> Option<usize> {
+        (self.chunks.len() < self.manifest.chunk_hashes.len()).then_some(self.chunks.len())
+    }
+
+    pub fn add_chunk(&mut self, index: usize, data: Vec<u8>) -> Result<(), NodeError> {
+        if Some(index) != self.next_chunk() {
+            return Err(NodeError::Blockchain(format!("Unexpected snapshot chunk {}", index)));
+        }
+        if data.len() > CHUNK_SIZE {
+            return Err(NodeError::Blockchain(format!("Snapshot chunk {} is larger than {} bytes", index, CHUNK_SIZE)));
+        }
+        if hex::encode(Sha256::digest(&data)) != self.manifest.chunk_hashes[index] {
+            return Err(NodeError::Blockchain(format!("Snapshot chunk {} does not match its hash", index)));
+        }
+        self.chunks.push(data);
+        Ok(())
+    }
+
+    /// Assembles the snapshot and checks that its accounts hash to the state root
+    /// in the snapshot block's header, then starts a chain from it.
+    pub fn finish(self, chain_id: u64, validators: HashSet<String>) -> Result<Blockchain, NodeError> {
+        if self.next_chunk().is_some() {
+            return Err(NodeError::Blockchain("Snapshot download is incomplete".into()));
+        }
+
+        let mut snapshot: PersistentState = serde_json::from_slice(&self.chunks.concat())?;
+        let block = match snapshot.blocks.pop() {
+            Some(block) if snapshot.blocks.is_empty()
+                && block.calculate_header_hash() == self.manifest.block.calculate_header_hash() => block,
+            _ => return Err(NodeError::Blockchain("Snapshot does not contain the block from its manifest".into())),
+        };
+
+        let accounts: BTreeMap<&str, _> = snapshot.state.iter().map(|(key, account)| (key.as_str(), account)).collect();
+        let state_root = compute_state_root(&accounts);
+        if state_root != block.header.state_root {
+            return Err(NodeError::Blockchain(format!(
+                "Snapshot state root mismatch. Expected: {}, Got: {}", block.header.state_root, state_root
+            )));
+        }
+
+        Ok(Blockchain::from_snapshot(chain_id, validators, block, snapshot.state))
+    }
+}
+
+// --- Unit Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::CommitSignature;
+    use crate::consensus::precommit_message;
+    use crate::blockchain::{account_key, AccountState};
+    use crate::genesis::{Genesis, GenesisAccount};
+    use crate::wallet::Wallet;
+    use ethers_core::types::{Address, U256};
+
+    /// A chain whose head commits to its current state, as every finalized block does.
+    fn chain_with_account() -> Blockchain {
+        let mut genesis = Genesis::with_validators(HashSet::new());
+        genesis.alloc.insert(Address::repeat_byte(7), GenesisAccount { balance: U256::from(42), ..Default::default() });
+        Blockchain::from_genesis(&genesis)
+    }
+
+    /// Captures the state at the chain's current head.
+    fn capture(chain: &Blockchain) -> Snapshot {
+        let head = chain.blocks.last().expect("Chain always contains a block").clone();
+        Snapshot::from_state(head, chain.state.clone()).unwrap()
+    }
+
+    fn empty_download(snapshot: &Snapshot) -> SnapshotDownload {
+        SnapshotDownload { manifest: snapshot.manifest.clone(), chunks: Vec::new() }
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let chain = chain_with_account();
+        let snapshot = capture(&chain);
+
+        let mut download = empty_download(&snapshot);
+        while let Some(index) = download.next_chunk() {
+            download.add_chunk(index, snapshot.chunk(index).unwrap().to_vec()).unwrap();
+        }
+        let restored = download.finish(chain.chain_id, HashSet::new()).unwrap();
+        assert_eq!(restored.state_root(), chain.state_root());
+        assert_eq!(restored.head_hash(), chain.head_hash());
+    }
+
+    #[test]
+    fn test_tampered_snapshot_is_rejected() {
+        let chain = chain_with_account();
+        let snapshot = capture(&chain);
+
+        let mut corrupted = snapshot.chunk(0).unwrap().to_vec();
+        corrupted[0] ^= 1;
+        assert!(empty_download(&snapshot).add_chunk(0, corrupted).is_err());
+
+        // Chunks that match their hashes but carry state the header doesn't commit to are rejected too.
+        let mut state = chain.state.clone();
+        state.insert(account_key(&[8; 20]), AccountState::default());
+        let forged_chain = Blockchain::from_parts(chain.chain_id, HashSet::new(), chain.blocks.clone(), state, HashMap::new());
+        let forged = capture(&forged_chain);
+        let mut download = empty_download(&forged);
+        while let Some(index) = download.next_chunk() {
+            download.add_chunk(index, forged.chunk(index).unwrap().to_vec()).unwrap();
+        }
+        assert!(download.finish(chain.chain_id, HashSet::new()).is_err());
+    }
+
+    #[test]
+    fn test_forged_manifest_is_rejected() {
+        let (validator, outsider) = (Wallet::new(), Wallet::new());
+        let mut genesis = Genesis::with_validators([validator.public_key_hex()].into_iter().collect());
+        genesis.alloc.insert(Address::repeat_byte(7), GenesisAccount { balance: U256::from(42), ..Default::default() });
+        let chain = Blockchain::from_genesis(&genesis);
+        let commit = |wallet: &Wallet, block: &Block| vec![CommitSignature {
+            validator_pubkey: wallet.public_key_hex(),
+            round: 0,
+            signature: wallet.sign(&precommit_message(block.header.id, 0, &block.calculate_header_hash())),
+        }];
+
+        // The block a snapshot is taken at must carry a quorum commit from the validator set.
+        let snapshot = capture(&chain);
+        assert!(SnapshotDownload::start(&chain, snapshot.manifest.clone()).is_err());
+        let mut manifest = snapshot.manifest.clone();
+        manifest.block.commit = commit(&outsider, &manifest.block);
+        assert!(SnapshotDownload::start(&chain, manifest.clone()).is_err());
+        manifest.block.commit = commit(&validator, &manifest.block);
+        assert!(SnapshotDownload::start(&chain, manifest).is_ok());
+
+        // A validly committed block whose state root the chunks don't match.
+        let mut block = snapshot.manifest.block.clone();
+        block.header.state_root = hex::encode([9u8; 32]);
+        block.commit = commit(&validator, &block);
+        let forged = Snapshot::from_state(block, chain.state.clone()).unwrap();
+        let mut download = SnapshotDownload::start(&chain, forged.manifest.clone()).unwrap();
+        while let Some(index) = download.next_chunk() {
+            download.add_chunk(index, forged.chunk(index).unwrap().to_vec()).unwrap();
+        }
+        let err = download.finish(chain.chain_id, chain.validator_set.clone()).err().unwrap();
+        assert!(err.to_string().contains("state root mismatch"));
+    }
+
+    #[test]
+    fn test_oversized_snapshot_is_rejected_before_download() {
+        let validator = Wallet::new();
+        let chain = Blockchain::from_genesis(&Genesis::with_validators([validator.public_key_hex()].into_iter().collect()));
+        let mut manifest = capture(&chain).manifest;
+        let hash = manifest.block.calculate_header_hash();
+        manifest.block.commit = vec![CommitSignature {
+            validator_pubkey: validator.public_key_hex(),
+            round: 0,
+            signature: validator.sign(&precommit_message(manifest.block.header.id, 0, &hash)),
+        }];
+
+        let mut too_many = manifest.clone();
+        too_many.chunk_hashes = vec![manifest.chunk_hashes[0].clone(); MAX_SNAPSHOT_CHUNKS + 1];
+        let err = SnapshotDownload::start(&chain, too_many).err().unwrap();
+        assert!(err.to_string().contains("more than the maximum"));
+
+        // A chunk over the size limit is refused even if the manifest lists its hash.
+        let oversized = vec![0u8; CHUNK_SIZE + 1];
+        manifest.chunk_hashes = vec![hex::encode(Sha256::digest(&oversized))];
+        let mut download = SnapshotDownload::start(&chain, manifest).unwrap();
+        assert!(download.add_chunk(0, oversized).is_err());
+    }
+}
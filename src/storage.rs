@@ -0,0 +1,307 @@
+use crate::block::{Block, Receipt};
+use crate::blockchain::{AccountState, Blockchain, MAX_REORG_DEPTH};
+use crate::errors::NodeError;
+use crate::genesis::Genesis;
+use crate::persistence;
+use sled::transaction::{ConflictableTransactionResult, TransactionError, TransactionalTree};
+use sled::Transactional;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+const HEAD_HEIGHT_KEY: &[u8] = b"head_height";
+
+/// The database directory inside the node's data directory.
+const DATABASE_DIR: &str = "chain_db";
+
+/// Durable storage the chain writes through to as blocks are accepted and reverted.
+pub trait ChainStore: Send + std::fmt::Debug {
+    /// Records a newly accepted block together with the accounts it changed, their previous
+    /// values (`undo`) and its receipts.
+    fn commit_block(
+        &self,
+        block: &Block,
+        accounts: &HashMap<String, Option<AccountState>>,
+        undo: &HashMap<String, Option<AccountState>>,
+        receipts: &[Receipt],
+    ) -> Result<(), NodeError>;
+
+    /// Records that the head block was removed in a reorg, restoring the accounts it changed.
+    fn revert_block(&self, block: &Block, restored: &HashMap<String, Option<AccountState>>) -> Result<(), NodeError>;
+
+    /// Replaces everything stored with `chain`.
+    fn reset(&self, chain: &Blockchain) -> Result<(), NodeError>;
+}
+
+/// The node's on-disk database. Each accepted block is committed in a single
+/// transaction across all trees, so a crash never leaves a half-written block behind.
+#[derive(Debug, Clone)]
+pub struct Storage {
+    db: sled::Db,
+    /// Blocks keyed by big-endian height.
+    blocks_by_height: sled::Tree,
+    /// Block heights keyed by header hash.
+    blocks_by_hash: sled::Tree,
+    /// Receipts keyed by transaction hash. A receipt locates its transaction in its block.
+    transactions: sled::Tree,
+    /// Account state keyed by `account_key`.
+    accounts: sled::Tree,
+    /// The previous values of the accounts each block changed, keyed by big-endian height.
+    /// Only the last `MAX_REORG_DEPTH` blocks are kept.
+    undo: sled::Tree,
+    metadata: sled::Tree,
+}
+
+impl Storage {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, NodeError> {
+        let db = sled::open(path).map_err(io::Error::from)?;
+        let tree = |name: &str| db.open_tree(name).map_err(io::Error::from);
+        Ok(Self {
+            blocks_by_height: tree("blocks_by_height")?,
+            blocks_by_hash: tree("blocks_by_hash")?,
+            transactions: tree("transactions")?,
+            accounts: tree("accounts")?,
+            undo: tree("undo")?,
+            metadata: tree("metadata")?,
+            db,
+        })
+    }
+
+    /// Loads the chain of `genesis`, or returns `None` if the database is empty.
+    pub fn load(&self, genesis: &Genesis) -> Result<Option<Blockchain>, NodeError> {
+        let mut blocks = Vec::new();
+        for entry in self.blocks_by_height.iter() {
+            let (_, value) = entry.map_err(io::Error::from)?;
+            blocks.push(serde_json::from_slice::<Block>(&value)?);
+        }
+        if blocks.is_empty() {
+            return Ok(None);
+        }
+
+        let mut state = HashMap::new();
+        for entry in self.accounts.iter() {
+            let (key, value) = entry.map_err(io::Error::from)?;
+            state.insert(String::from_utf8_lossy(&key).into_owned(), serde_json::from_slice(&value)?);
+        }
+        let mut receipts = HashMap::new();
+        for entry in self.transactions.iter() {
+            let (_, value) = entry.map_err(io::Error::from)?;
+            let receipt: Receipt = serde_json::from_slice(&value)?;
+            receipts.insert(receipt.tx_hash.clone(), receipt);
+        }
+        let mut undo_log = HashMap::new();
+        for entry in self.undo.iter() {
+            let (key, value) = entry.map_err(io::Error::from)?;
+            let height = u64::from_be_bytes(key.as_ref().try_into().map_err(|_| persistence::corrupted("undo key is not a height"))?);
+            undo_log.insert(height, serde_json::from_slice(&value)?);
+        }
+
+        let chain = Blockchain::from_parts(genesis.chain_id, genesis.validator_set(), blocks, state, receipts);
+        Ok(Some(chain.with_undo_log(undo_log)))
+    }
+
+    fn flush(&self) -> Result<(), NodeError> {
+        self.db.flush().map_err(io::Error::from)?;
+        Ok(())
+    }
+}
+
+impl ChainStore for Storage {
+    /// Writes the block, its accounts, undo data and receipts in a single transaction.
+    fn commit_block(
+        &self,
+        block: &Block,
+        accounts: &HashMap<String, Option<AccountState>>,
+        undo: &HashMap<String, Option<AccountState>>,
+        receipts: &[Receipt],
+    ) -> Result<(), NodeError> {
+        let height = block.header.id.to_be_bytes();
+        let expired_undo = block.header.id.checked_sub(MAX_REORG_DEPTH).map(u64::to_be_bytes);
+        let hash = block.calculate_header_hash();
+        let block_data = serde_json::to_vec(block)?;
+        let accounts = encode_accounts(accounts)?;
+        let undo_data = serde_json::to_vec(undo)?;
+        let receipts = receipts.iter()
+            .map(|receipt| Ok((receipt.tx_hash.clone(), serde_json::to_vec(receipt)?)))
+            .collect::<Result<Vec<_>, serde_json::Error>>()?;
+
+        (&self.blocks_by_height, &self.blocks_by_hash, &self.transactions, &self.accounts, &self.undo, &self.metadata)
+            .transaction(|(by_height, by_hash, transactions, account_tree, undo_tree, metadata)| -> ConflictableTransactionResult<()> {
+                by_height.insert(&height, block_data.as_slice())?;
+                by_hash.insert(hash.as_bytes(), &height)?;
+                for (tx_hash, receipt) in &receipts {
+                    transactions.insert(tx_hash.as_bytes(), receipt.as_slice())?;
+                }
+                write_accounts(account_tree, &accounts)?;
+                undo_tree.insert(&height, undo_data.as_slice())?;
+                if let Some(expired) = &expired_undo {
+                    undo_tree.remove(expired)?;
+                }
+                metadata.insert(HEAD_HEIGHT_KEY, &height)?;
+                Ok(())
+            })
+            .map_err(transaction_error)?;
+        self.flush()
+    }
+
+    fn revert_block(&self, block: &Block, restored: &HashMap<String, Option<AccountState>>) -> Result<(), NodeError> {
+        let height = block.header.id.to_be_bytes();
+        let parent_height = block.header.id.saturating_sub(1).to_be_bytes();
+        let hash = block.calculate_header_hash();
+        let accounts = encode_accounts(restored)?;
+
+        (&self.blocks_by_height, &self.blocks_by_hash, &self.transactions, &self.accounts, &self.undo, &self.metadata)
+            .transaction(|(by_height, by_hash, transactions, account_tree, undo_tree, metadata)| -> ConflictableTransactionResult<()> {
+                by_height.remove(&height)?;
+                by_hash.remove(hash.as_bytes())?;
+                for tx in &block.transactions {
+                    transactions.remove(tx.hash.as_bytes())?;
+                }
+                write_accounts(account_tree, &accounts)?;
+                undo_tree.remove(&height)?;
+                metadata.insert(HEAD_HEIGHT_KEY, &parent_height)?;
+                Ok(())
+            })
+            .map_err(transaction_error)?;
+        self.flush()
+    }
+
+    /// Used to initialize an empty database, to migrate a legacy JSON
+    /// state file and after restoring a state snapshot. Old and new contents are
+    /// swapped in a single transaction, so a crash leaves one or the other.
+    fn reset(&self, chain: &Blockchain) -> Result<(), NodeError> {
+        let trees = [&self.blocks_by_height, &self.blocks_by_hash, &self.transactions, &self.accounts, &self.undo, &self.metadata];
+        let mut stale = Vec::with_capacity(trees.len());
+        for tree in trees {
+            stale.push(tree.iter().keys().collect::<Result<Vec<_>, _>>().map_err(io::Error::from)?);
+        }
+
+        let blocks = chain.blocks.iter()
+            .map(|block| Ok((block.header.id.to_be_bytes(), block.calculate_header_hash(), serde_json::to_vec(block)?)))
+            .collect::<Result<Vec<_>, serde_json::Error>>()?;
+        let accounts = chain.state.iter()
+            .map(|(key, account)| Ok((key.clone(), serde_json::to_vec(account)?)))
+            .collect::<Result<Vec<_>, serde_json::Error>>()?;
+        let receipts = chain.receipts.iter()
+            .map(|(tx_hash, receipt)| Ok((tx_hash.clone(), serde_json::to_vec(receipt)?)))
+            .collect::<Result<Vec<_>, serde_json::Error>>()?;
+        let undo = chain.undo_log().iter()
+            .map(|(height, undo)| Ok((height.to_be_bytes(), serde_json::to_vec(undo)?)))
+            .collect::<Result<Vec<_>, serde_json::Error>>()?;
+        let head_height = chain.height().to_be_bytes();
+
+        (&self.blocks_by_height, &self.blocks_by_hash, &self.transactions, &self.accounts, &self.undo, &self.metadata)
+            .transaction(|(by_height, by_hash, transactions, account_tree, undo_tree, metadata)| -> ConflictableTransactionResult<()> {
+                for (tree, keys) in [by_height, by_hash, transactions, account_tree, undo_tree, metadata].into_iter().zip(&stale) {
+                    for key in keys {
+                        tree.remove(key)?;
+                    }
+                }
+                for (height, hash, block) in &blocks {
+                    by_height.insert(height, block.as_slice())?;
+                    by_hash.insert(hash.as_bytes(), height)?;
+                }
+                for (key, account) in &accounts {
+                    account_tree.insert(key.as_bytes(), account.as_slice())?;
+                }
+                for (tx_hash, receipt) in &receipts {
+                    transactions.insert(tx_hash.as_bytes(), receipt.as_slice())?;
+                }
+                for (height, undo) in &undo {
+                    undo_tree.insert(height, undo.as_slice())?;
+                }
+                metadata.insert(HEAD_HEIGHT_KEY, &head_height)?;
+                Ok(())
+            })
+            .map_err(transaction_error)?;
+        self.flush()
+    }
+}
+
+/// Whether `data_dir` already holds a database.
+pub fn database_exists(data_dir: &Path) -> bool {
+    data_dir.join(DATABASE_DIR).exists()
+}
+
+/// Opens the database in `data_dir` and loads the chain from it. An empty database is
+/// initialized from a legacy JSON state file in `data_dir` if one exists, or from `genesis`.
+pub fn open_chain(data_dir: &Path, genesis: &Genesis) -> Result<Blockchain, NodeError> {
+    let storage = Storage::open(data_dir.join(DATABASE_DIR))?;
+    let mut chain = match storage.load(genesis)? {
+        Some(chain) => {
+            genesis.check(&chain)?;
+            println!("[Storage] Loaded chain from database. Current block height: {}", chain.height());
+            chain
+        }
+        None => {
+            let chain = persistence::load_or_initialize_state(genesis, data_dir)?;
+            storage.reset(&chain)?;
+            println!("[Storage] Initialized database at block height {}.", chain.height());
+            chain
+        }
+    };
+    chain.set_storage(Box::new(storage));
+    Ok(chain)
+}
+
+type EncodedAccounts = Vec<(String, Option<Vec<u8>>)>;
+
+fn encode_accounts(accounts: &HashMap<String, Option<AccountState>>) -> Result<EncodedAccounts, NodeError> {
+    accounts.iter()
+        .map(|(key, account)| Ok((key.clone(), account.as_ref().map(serde_json::to_vec).transpose()?)))
+        .collect()
+}
+
+fn write_accounts(tree: &TransactionalTree, accounts: &EncodedAccounts) -> ConflictableTransactionResult<()> {
+    for (key, account) in accounts {
+        match account {
+            Some(data) => { tree.insert(key.as_bytes(), data.as_slice())?; }
+            None => { tree.remove(key.as_bytes())?; }
+        }
+    }
+    Ok(())
+}
+
+fn transaction_error(e: TransactionError<()>) -> NodeError {
+    match e {
+        TransactionError::Storage(e) => NodeError::Persistence(e.into()),
+        TransactionError::Abort(()) => NodeError::Persistence(io::Error::other("Storage transaction aborted")),
+    }
+}
+
+// --- Unit Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::account_key;
+    use revm::primitives::U256;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_committed_block_survives_reopen() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let chain = Blockchain::from_genesis(&Genesis::with_validators(HashSet::new()));
+        let storage = Storage::open(dir.path()).unwrap();
+        storage.reset(&chain).unwrap();
+
+        let mut block = chain.blocks[0].clone();
+        block.header.id = 1;
+        block.header.previous_hash = chain.head_hash();
+        let key = account_key(&[1; 20]);
+        let changes = HashMap::from([(key.clone(), Some(AccountState { balance: U256::from(5), ..Default::default() }))]);
+        storage.commit_block(&block, &changes, &HashMap::from([(key.clone(), None)]), &[]).unwrap();
+        drop(storage);
+
+        let genesis = Genesis::with_validators(HashSet::new());
+        let loaded = Storage::open(dir.path()).unwrap().load(&genesis).unwrap().unwrap();
+        assert_eq!(loaded.height(), 1);
+        assert_eq!(loaded.state[&key].balance, U256::from(5));
+        assert_eq!(loaded.undo_log()[&1].get(&key), Some(&None));
+
+        let storage = Storage::open(dir.path()).unwrap();
+        storage.revert_block(&block, &HashMap::from([(key.clone(), None)])).unwrap();
+        let reverted = storage.load(&genesis).unwrap().unwrap();
+        assert_eq!(reverted.height(), 0);
+        assert!(!reverted.state.contains_key(&key));
+    }
+}
@@ -0,0 +1,152 @@
+use crate::block::{Block, BlockHeader};
+use crate::blockchain::Blockchain;
+use crate::snapshot::{Snapshot, SnapshotDownload, SnapshotManifest};
+use libp2p::{PeerId, StreamProtocol};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The request-response protocol nodes use to catch up with their peers.
+pub const SYNC_PROTOCOL: StreamProtocol = StreamProtocol::new("/kosher-chain/sync/1");
+
+/// The most blocks or headers served for a single request.
+pub const MAX_BLOCKS_PER_REQUEST: u64 = 64;
+
+/// How many headers below our head are requested to find the block we have in common with a peer.
+const HEADER_LOOKBACK: u64 = 16;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SyncRequest {
+    /// Asks for the peer's chain height and head.
+    Status,
+    /// Asks for the canonical headers from `from` to `to`, inclusive.
+    GetHeaders { from: u64, to: u64 },
+    /// Asks for the canonical blocks from `from` to `to`, inclusive.
+    GetBlocks { from: u64, to: u64 },
+    /// Asks for the manifest of the peer's latest state snapshot.
+    GetSnapshotManifest,
+    /// Asks for one chunk of the snapshot taken at `height`.
+    GetSnapshotChunk { height: u64, index: usize },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SyncResponse {
+    Status {
+        height: u64,
+        head_hash: String,
+        /// The height of the latest state snapshot the peer can serve.
+        #[serde(default)]
+        snapshot_height: Option<u64>,
+    },
+    Headers(Vec<BlockHeader>),
+    Blocks(Vec<Block>),
+    SnapshotManifest(Option<Box<SnapshotManifest>>),
+    /// A hex-encoded snapshot chunk, or `None` if the peer no longer has that snapshot.
+    SnapshotChunk { height: u64, index: usize, data: Option<String> },
+}
+
+/// Sync bookkeeping kept by the P2P task.
+#[derive(Default)]
+pub struct SyncState {
+    /// The last height each peer reported, used to decide how far to sync from it.
+    pub peer_heights: HashMap<PeerId, u64>,
+    /// The latest snapshot this node serves. Only validators produce snapshots.
+    pub snapshot: Option<Snapshot>,
+    /// A snapshot being downloaded, and the peer it is downloaded from.
+    pub download: Option<(PeerId, SnapshotDownload)>,
+}
+
+/// Answers a sync request from the local chain and snapshot. Ranges are
+/// clamped to the chain height and to `MAX_BLOCKS_PER_REQUEST`.
+pub fn answer(blockchain: &Blockchain, snapshot: Option<&Snapshot>, request: SyncRequest) -> SyncResponse {
+    match request {
+        SyncRequest::Status => SyncResponse::Status {
+            height: blockchain.height(),
+            head_hash: blockchain.head_hash(),
+            snapshot_height: snapshot.map(Snapshot::height),
+        },
+        SyncRequest::GetHeaders { from, to } => SyncResponse::Headers(
+            blocks_in_range(blockchain, from, to).map(|block| block.header.clone()).collect(),
+        ),
+        SyncRequest::GetBlocks { from, to } => SyncResponse::Blocks(
+            blocks_in_range(blockchain, from, to).cloned().collect(),
+        ),
+        SyncRequest::GetSnapshotManifest => SyncResponse::SnapshotManifest(
+            snapshot.map(|snapshot| Box::new(snapshot.manifest.clone())),
+        ),
+        SyncRequest::GetSnapshotChunk { height, index } => SyncResponse::SnapshotChunk {
+            height,
+            index,
+            data: snapshot
+                .filter(|snapshot| snapshot.height() == height)
+                .and_then(|snapshot| snapshot.chunk(index))
+                .map(hex::encode),
+        },
+    }
+}
+
+fn blocks_in_range(blockchain: &Blockchain, from: u64, to: u64) -> impl Iterator<Item = &Block> {
+    let to = to.min(from.saturating_add(MAX_BLOCKS_PER_REQUEST - 1));
+    // Nodes started from a snapshot have no blocks below it.
+    (from..=to).filter_map(|height| blockchain.block_by_height(height))
+}
+
+/// Whether a new node should start from a peer's snapshot rather than replay every block.
+/// Nodes that already hold blocks keep their history and sync block by block.
+pub fn should_fetch_snapshot(local_height: u64, snapshot_height: Option<u64>) -> bool {
+    local_height == 0 && snapshot_height.is_some_and(|height| height > MAX_BLOCKS_PER_REQUEST)
+}
+
+/// The headers request used to find where our chain and a peer's chain diverge.
+pub fn common_ancestor_request(local_height: u64) -> SyncRequest {
+    SyncRequest::GetHeaders {
+        from: local_height.saturating_sub(HEADER_LOOKBACK),
+        to: local_height,
+    }
+}
+
+/// Finds the highest of a peer's headers that matches our canonical chain.
+/// If none of them match, the peer forked off below the lookback window and
+/// syncing restarts just below the lowest header received.
+pub fn common_ancestor(blockchain: &Blockchain, headers: &[BlockHeader]) -> u64 {
+    headers.iter().rev()
+        .find(|header| {
+            let hash = Block::header_hash(header);
+            blockchain.block_by_height(header.id).is_some_and(|block| block.calculate_header_hash() == hash)
+        })
+        .map(|header| header.id)
+        .unwrap_or_else(|| headers.first().map_or(0, |header| header.id.saturating_sub(1)))
+}
+
+/// The next block range to download from a peer at `peer_height`, starting above `synced_height`.
+/// Returns `None` once we have everything the peer has.
+pub fn next_blocks_request(synced_height: u64, peer_height: u64) -> Option<SyncRequest> {
+    if synced_height >= peer_height {
+        return None;
+    }
+    let from = synced_height + 1;
+    Some(SyncRequest::GetBlocks {
+        from,
+        to: peer_height.min(from + MAX_BLOCKS_PER_REQUEST - 1),
+    })
+}
+
+// --- Unit Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_requests_cover_the_gap_in_batches() {
+        assert!(next_blocks_request(10, 10).is_none());
+        assert!(next_blocks_request(12, 10).is_none());
+
+        match next_blocks_request(10, 12) {
+            Some(SyncRequest::GetBlocks { from, to }) => assert_eq!((from, to), (11, 12)),
+            other => panic!("unexpected request: {:?}", other),
+        }
+        match next_blocks_request(0, 1_000) {
+            Some(SyncRequest::GetBlocks { from, to }) => assert_eq!((from, to), (1, MAX_BLOCKS_PER_REQUEST)),
+            other => panic!("unexpected request: {:?}", other),
+        }
+    }
+}
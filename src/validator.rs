@@ -1,44 +1,230 @@
 use crate::block::{Block, BlockHeader, Transaction};
-use crate::blockchain::Blockchain;
+use crate::blockchain::{Blockchain, BLOCK_GAS_LIMIT};
+use crate::consensus::{proposer_for, Vote, VoteCollector, VoteKind, VoteOutcome};
 use crate::mempool::Mempool;
 use crate::p2p::ChainMessage;
 use crate::wallet::Wallet;
+use std::collections::HashSet;
 use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc;
-use tokio::time::{sleep, Duration};
+use tokio::time::Duration;
 use chrono::Utc;
 use tracing::{info, warn, error, debug};
 
-const BLOCK_PROPOSAL_INTERVAL_SECONDS: u64 = 15;
+/// How long a round lasts. If no block is finalized at the current height within
+/// this time, the round is advanced and the next validator in turn proposes.
+const ROUND_TIMEOUT_SECONDS: u64 = 15;
 
 pub struct ValidatorService {
     wallet: Wallet,
     blockchain: Arc<Mutex<Blockchain>>,
     mempool: Arc<Mutex<Mempool>>,
     p2p_tx: mpsc::Sender<ChainMessage>,
+    /// Proposals and votes from other validators, forwarded by the P2P service.
+    consensus_rx: mpsc::Receiver<ChainMessage>,
+    votes: VoteCollector,
+    /// The height being decided and the current round at that height.
+    height: u64,
+    round: u32,
+    /// The (height, round, block hash) of the last polka this validator precommitted. It only
+    /// prevotes for that block at that height until it sees a polka for another block in a later round.
+    locked: Option<(u64, u32, String)>,
+    /// The (height, round) this validator last prevoted and precommitted in. It votes once per step.
+    prevoted: Option<(u64, u32)>,
+    precommitted: Option<(u64, u32)>,
 }
 
 impl ValidatorService {
-    // ... new() function ...
+    pub fn new(
+        wallet: Wallet,
+        blockchain: Arc<Mutex<Blockchain>>,
+        mempool: Arc<Mutex<Mempool>>,
+        p2p_tx: mpsc::Sender<ChainMessage>,
+        consensus_rx: mpsc::Receiver<ChainMessage>,
+    ) -> Self {
+        Self {
+            wallet,
+            blockchain,
+            mempool,
+            p2p_tx,
+            consensus_rx,
+            votes: VoteCollector::new(),
+            height: 0,
+            round: 0,
+            locked: None,
+            prevoted: None,
+            precommitted: None,
+        }
+    }
 
-    pub async fn run(&self) {
-        info!(interval = %BLOCK_PROPOSAL_INTERVAL_SECONDS, "Starting block proposal service.");
-        let mut interval = tokio::time::interval(Duration::from_secs(BLOCK_PROPOSAL_INTERVAL_SECONDS));
+    pub async fn run(mut self) {
+        info!(timeout = %ROUND_TIMEOUT_SECONDS, "Starting block proposal service.");
+        let mut interval = tokio::time::interval(Duration::from_secs(ROUND_TIMEOUT_SECONDS));
 
         loop {
-            interval.tick().await;
+            tokio::select! {
+                _ = interval.tick() => self.on_round_timeout().await,
+                Some(msg) = self.consensus_rx.recv() => match msg {
+                    ChainMessage::Proposal(block) => self.handle_proposal(block).await,
+                    ChainMessage::Vote(vote) => self.handle_vote(vote).await,
+                    _ => {}
+                },
+            }
+        }
+    }
+
+    /// Called once per round timeout. Starts round 0 when the chain has moved to a new
+    /// height, otherwise performs a view change to the next round, then proposes if it
+    /// is this validator's turn.
+    async fn on_round_timeout(&mut self) {
+        let (next_height, is_proposer) = {
+            let chain = self.blockchain.lock().unwrap();
+            let next_height = chain.height() + 1;
+            if next_height != self.height {
+                self.height = next_height;
+                self.round = 0;
+            } else {
+                self.round += 1;
+                debug!(height = %self.height, round = %self.round, "Round timed out. Changing view.");
+            }
+            let proposer = proposer_for(&chain.validator_set, self.height, self.round);
+            (next_height, proposer == Some(&self.wallet.public_key_hex()))
+        };
+
+        if is_proposer {
+            debug!(height = %next_height, round = %self.round, "This validator is the proposer.");
             self.propose_block().await;
         }
     }
 
-    async fn propose_block(&self) {
+    /// Validates a proposal and, if it is valid and this validator is not locked on
+    /// another block at the same height, prevotes for it.
+    async fn handle_proposal(&mut self, block: Block) {
+        let (height, round) = (block.header.id, block.header.round);
+        if height == self.height && round < self.round {
+            debug!(height = %height, round = %round, "Ignoring proposal from an earlier round.");
+            return;
+        }
+        let block_hash = block.calculate_header_hash();
+
+        let (finalized, validator_set, quorum) = {
+            let chain = self.blockchain.lock().unwrap();
+            if let Err(e) = chain.validate_proposal(&block) {
+                warn!(height = %block.header.id, "Rejected block proposal: {}", e);
+                return;
+            }
+            let finalized = self.votes.add_proposal(block.clone(), &chain.validator_set, chain.quorum_size());
+            (finalized, chain.validator_set.clone(), chain.quorum_size())
+        };
+        if let Some(block) = finalized {
+            self.finalize(block).await;
+            return;
+        }
+
+        // The proposal is signed by the scheduled proposer of its round, so that validator has
+        // changed view. Validators that timed out sooner may be ahead; catch up with them.
+        if height == self.height && round > self.round {
+            self.round = round;
+        }
+
+        if let Some((locked_height, locked_round, locked_hash)) = &self.locked {
+            if *locked_height == height && *locked_hash != block_hash {
+                debug!(height = %height, round = %round, locked_round = %locked_round, "Locked on block {}. Not voting for {}", locked_hash, block_hash);
+                return;
+            }
+        }
+        if self.prevoted == Some((height, round)) {
+            return;
+        }
+
+        let vote = Vote::sign(&self.wallet, VoteKind::Prevote, height, round, block_hash);
+        self.prevoted = Some((height, round));
+        debug!(height = %vote.height, round = %vote.round, "Prevoting for block {}", vote.block_hash);
+        self.broadcast(ChainMessage::Vote(vote.clone())).await;
+        self.record_vote(vote, &validator_set, quorum).await;
+    }
+
+    async fn handle_vote(&mut self, vote: Vote) {
+        let (validator_set, quorum) = {
+            let chain = self.blockchain.lock().unwrap();
+            (chain.validator_set.clone(), chain.quorum_size())
+        };
+        self.record_vote(vote, &validator_set, quorum).await;
+    }
+
+    async fn record_vote(&mut self, vote: Vote, validator_set: &HashSet<String>, quorum: usize) {
+        match self.votes.add_vote(vote, validator_set, quorum) {
+            VoteOutcome::Pending => {}
+            VoteOutcome::Polka { height, round, block_hash } => self.handle_polka(height, round, block_hash, validator_set, quorum).await,
+            VoteOutcome::Finalized(block) => self.finalize(*block).await,
+        }
+    }
+
+    /// Locks on a block a quorum prevoted for and precommits it. A polka for another block in a
+    /// later round than the lock releases it: a quorum has moved on, so the locked block can't
+    /// be committed without validators that locked on the newer block.
+    async fn handle_polka(&mut self, height: u64, round: u32, block_hash: String, validator_set: &HashSet<String>, quorum: usize) {
+        if height != self.height {
+            return;
+        }
+        if let Some((locked_height, locked_round, locked_hash)) = &self.locked {
+            if *locked_height == height && (*locked_round > round || (*locked_round == round && *locked_hash != block_hash)) {
+                return;
+            }
+            if *locked_height == height && *locked_hash != block_hash {
+                info!(height = %height, round = %round, locked_round = %locked_round, "Polka for block {} in a later round. Unlocking from {}", block_hash, locked_hash);
+            }
+        }
+        if round > self.round {
+            self.round = round;
+        }
+        self.locked = Some((height, round, block_hash.clone()));
+        if self.precommitted == Some((height, round)) {
+            return;
+        }
+
+        let vote = Vote::sign(&self.wallet, VoteKind::Precommit, height, round, block_hash);
+        self.precommitted = Some((height, round));
+        debug!(height = %vote.height, round = %vote.round, "Precommitting block {}", vote.block_hash);
+        self.broadcast(ChainMessage::Vote(vote.clone())).await;
+        if let VoteOutcome::Finalized(block) = self.votes.add_vote(vote, validator_set, quorum) {
+            self.finalize(*block).await;
+        }
+    }
+
+    /// Imports a block that has collected a quorum commit and broadcasts it.
+    async fn finalize(&mut self, block: Block) {
+        let height = block.header.id;
+        let imported = {
+            let mut chain = self.blockchain.lock().unwrap();
+            let imported = chain.validate_and_add_block(block.clone());
+            if imported.is_ok() {
+                self.mempool.lock().unwrap().revalidate(&chain);
+            }
+            imported
+        };
+        match imported {
+            Ok(()) => {
+                info!(height = %height, signatures = %block.commit.len(), "Block finalized.");
+                self.broadcast(ChainMessage::Block(block)).await;
+            }
+            Err(e) => warn!(height = %height, "Failed to import finalized block: {}", e),
+        }
+        self.votes.prune(self.blockchain.lock().unwrap().height());
+    }
+
+    async fn broadcast(&self, msg: ChainMessage) {
+        if let Err(e) = self.p2p_tx.send(msg).await {
+            error!("Failed to send message to P2P service: {}", e);
+        }
+    }
+
+    async fn propose_block(&mut self) {
         let transactions = {
             let mut mempool = self.mempool.lock().unwrap();
-            let txs = mempool.get_transactions(100);
-            if !txs.is_empty() {
-                mempool.clear(&txs);
-            }
-            txs
+            mempool.remove_expired();
+            // The transactions stay pooled until the block is finalized, in case this round fails.
+            select_within_gas_limit(mempool.get_transactions(100))
         };
 
         if transactions.is_empty() {
@@ -49,32 +235,186 @@ impl ValidatorService {
         info!(num_txs = %transactions.len(), "Proposing new block...");
 
         let new_block = {
-            // ... block creation logic ...
-        };
+            let chain = self.blockchain.lock().unwrap();
+            let previous_block = chain.blocks.last().expect("Chain always contains a genesis block");
+            let mut header = BlockHeader {
+                id: previous_block.header.id + 1,
+                round: self.round,
+                timestamp: Utc::now().timestamp(),
+                previous_hash: previous_block.calculate_header_hash(),
+                validator_pubkey: self.wallet.public_key_hex(),
+                transactions_hash: String::new(),
+                receipts_root: String::new(),
+                state_root: String::new(),
+            };
 
-        if let Err(e) = self.p2p_tx.send(ChainMessage::Block(new_block)).await {
-            error!("Failed to send block to P2P service: {}", e);
-        } else {
-            info!("New block sent to P2P network for broadcast.");
-        }
-    }
-}                    validator_pubkey: self.wallet.public_key_hex(),
-                    transactions_hash: Block::hash_transactions(&transactions),
-                },
+            // Execute the candidates so the header can commit to their receipts.
+            let (transactions, execution) = match chain.execute_candidates(&header, transactions) {
+                Ok(result) => result,
+                Err(e) => {
+                    error!("Failed to execute block candidates: {}", e);
+                    return;
+                }
+            };
+            if transactions.is_empty() {
+                warn!("No executable transactions. Skipping block proposal.");
+                return;
+            }
+            header.transactions_hash = Block::hash_transactions(&transactions);
+            header.receipts_root = Block::hash_receipts(&execution.receipts);
+            header.state_root = chain.state_root_after(&execution.changes);
+
+            let mut block = Block {
+                header,
                 transactions,
-                signature: ed25519_dalek::Signature::from_bytes(&[0; 64]).unwrap(),
+                signature: ed25519_dalek::Signature::from_bytes(&[0; 64]),
+                commit: vec![],
             };
-            
+
             let block_hash = block.calculate_header_hash();
             block.signature = self.wallet.sign(block_hash.as_bytes());
             block
         };
 
-        // Send the new block to the P2P task to be gossiped to the network.
-        if let Err(e) = self.p2p_tx.send(ChainMessage::Block(new_block)).await {
-            eprintln!("[Validator] Failed to send block to P2P service: {}", e);
-        } else {
-            println!("[Validator] New block sent to P2P network for broadcast.");
+        // Gossip does not deliver our own messages back to us, so the proposal is handled locally too.
+        self.broadcast(ChainMessage::Proposal(new_block.clone())).await;
+        info!("New block proposal sent to P2P network for broadcast.");
+        self.handle_proposal(new_block).await;
+    }
+}
+
+/// Takes transactions in order until the next one would exceed `BLOCK_GAS_LIMIT`.
+/// Transactions that don't fit stay in the mempool for a later block.
+fn select_within_gas_limit(candidates: Vec<Transaction>) -> Vec<Transaction> {
+    let mut gas_used: u64 = 0;
+    candidates
+        .into_iter()
+        .take_while(|tx| match gas_used.checked_add(tx.gas_limit) {
+            Some(total) if total <= BLOCK_GAS_LIMIT => {
+                gas_used = total;
+                true
+            }
+            _ => false,
+        })
+        .collect()
+}
+
+// --- Unit Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{TransactionAction, KOSHER_CHAIN_ID};
+    use crate::genesis::Genesis;
+    use ethers_core::types::{Address, U256};
+
+    /// An empty block at the next height, signed by the scheduled proposer of `round`.
+    fn proposal(chain: &Blockchain, wallets: &[&Wallet], round: u32) -> Block {
+        let previous_block = chain.blocks.last().unwrap();
+        let header = BlockHeader {
+            id: previous_block.header.id + 1,
+            round,
+            timestamp: previous_block.header.timestamp + 1,
+            previous_hash: previous_block.calculate_header_hash(),
+            validator_pubkey: proposer_for(&chain.validator_set, previous_block.header.id + 1, round).unwrap().clone(),
+            transactions_hash: Block::hash_transactions(&[]),
+            receipts_root: Block::hash_receipts(&[]),
+            state_root: chain.state_root(),
+        };
+        let proposer = wallets.iter().find(|wallet| wallet.public_key_hex() == header.validator_pubkey).unwrap();
+        let mut block = Block { header, transactions: vec![], signature: ed25519_dalek::Signature::from_bytes(&[0; 64]), commit: vec![] };
+        let hash = block.calculate_header_hash();
+        block.signature = proposer.sign(hash.as_bytes());
+        block
+    }
+
+    /// Re-signs `block` as its proposer, e.g. after changing the header to equivocate.
+    fn resigned(mut block: Block, wallets: &[&Wallet]) -> Block {
+        let proposer = wallets.iter().find(|wallet| wallet.public_key_hex() == block.header.validator_pubkey).unwrap();
+        let hash = block.calculate_header_hash();
+        block.signature = proposer.sign(hash.as_bytes());
+        block
+    }
+
+    fn sent_vote(p2p_rx: &mut mpsc::Receiver<ChainMessage>) -> Option<Vote> {
+        match p2p_rx.try_recv() {
+            Ok(ChainMessage::Vote(vote)) => Some(vote),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_selection_stops_before_the_block_gas_limit() {
+        let user = Wallet::new();
+        let transfer = |nonce, gas_limit| {
+            let action = TransactionAction::Transfer { recipient: Address::repeat_byte(0x22), amount: U256::one() };
+            Transaction::new_signed(&user, KOSHER_CHAIN_ID, action, nonce, gas_limit, U256::one())
+        };
+        let candidates = vec![transfer(0, BLOCK_GAS_LIMIT - 42_000), transfer(1, 21_000), transfer(2, 21_001), transfer(3, 21_000)];
+
+        let selected = select_within_gas_limit(candidates.clone());
+        assert_eq!(selected, candidates[..2]);
+        assert!(selected.iter().map(|tx| tx.gas_limit).sum::<u64>() <= BLOCK_GAS_LIMIT);
+    }
+
+    #[tokio::test]
+    async fn test_split_votes_over_several_rounds_finalize_a_block() {
+        let me = Wallet::new();
+        let others: Vec<Wallet> = (0..3).map(|_| Wallet::new()).collect();
+        let wallets: Vec<&Wallet> = [&me].into_iter().chain(&others).collect();
+        let genesis = Genesis::with_validators(wallets.iter().map(|wallet| wallet.public_key_hex()).collect());
+        let blockchain = Arc::new(Mutex::new(Blockchain::from_genesis(&genesis)));
+        let (round_0, equivocation, round_1, round_2) = {
+            let chain = blockchain.lock().unwrap();
+            let round_0 = proposal(&chain, &wallets, 0);
+            let mut equivocation = round_0.clone();
+            equivocation.header.timestamp += 1;
+            let equivocation = resigned(equivocation, &wallets);
+            (round_0, equivocation, proposal(&chain, &wallets, 1), proposal(&chain, &wallets, 2))
+        };
+        let [hash_0, equivocation_hash, hash_1, hash_2] = [&round_0, &equivocation, &round_1, &round_2].map(Block::calculate_header_hash);
+        let vote = |wallet: &Wallet, kind, round, hash: &str| Vote::sign(wallet, kind, 1, round, hash.to_string());
+
+        let (p2p_tx, mut p2p_rx) = mpsc::channel(8);
+        let (_consensus_tx, consensus_rx) = mpsc::channel(8);
+        let mut validator = ValidatorService::new(me, Arc::clone(&blockchain), Arc::new(Mutex::new(Mempool::new())), p2p_tx, consensus_rx);
+        validator.height = 1;
+
+        // Round 0: the proposer equivocates and the prevotes split, so there is no polka and no lock.
+        validator.handle_proposal(round_0).await;
+        assert!(sent_vote(&mut p2p_rx).is_some_and(|vote| vote.kind == VoteKind::Prevote && vote.block_hash == hash_0));
+        validator.handle_vote(vote(&others[0], VoteKind::Prevote, 0, &hash_0)).await;
+        validator.handle_vote(vote(&others[1], VoteKind::Prevote, 0, &equivocation_hash)).await;
+        validator.handle_vote(vote(&others[2], VoteKind::Prevote, 0, &equivocation_hash)).await;
+        assert!(validator.locked.is_none());
+
+        // Round 1: the validator completes a polka and locks, but the other precommits are lost.
+        validator.handle_vote(vote(&others[0], VoteKind::Prevote, 1, &hash_1)).await;
+        validator.handle_vote(vote(&others[1], VoteKind::Prevote, 1, &hash_1)).await;
+        validator.handle_proposal(round_1).await;
+        assert!(sent_vote(&mut p2p_rx).is_some_and(|vote| vote.kind == VoteKind::Prevote && vote.block_hash == hash_1));
+        assert!(sent_vote(&mut p2p_rx).is_some_and(|vote| vote.kind == VoteKind::Precommit && vote.round == 1 && vote.block_hash == hash_1));
+        assert_eq!(validator.locked, Some((1, 1, hash_1.clone())));
+
+        // Round 2: locked on the round 1 block, the validator won't prevote another proposal...
+        validator.handle_proposal(round_2).await;
+        assert_eq!(validator.round, 2);
+        assert!(sent_vote(&mut p2p_rx).is_none());
+
+        // ...until the other validators form a polka for it in the later round, which releases the lock.
+        for wallet in &others {
+            validator.handle_vote(vote(wallet, VoteKind::Prevote, 2, &hash_2)).await;
+        }
+        assert_eq!(validator.locked, Some((1, 2, hash_2.clone())));
+        assert!(sent_vote(&mut p2p_rx).is_some_and(|vote| vote.kind == VoteKind::Precommit && vote.round == 2 && vote.block_hash == hash_2));
+
+        // Precommits from a quorum in round 2 finalize the block.
+        for wallet in &others[..2] {
+            validator.handle_vote(vote(wallet, VoteKind::Precommit, 2, &hash_2)).await;
         }
+        let chain = blockchain.lock().unwrap();
+        assert_eq!(chain.height(), 1);
+        assert_eq!(chain.head_hash(), hash_2);
+        assert_eq!(chain.blocks[1].commit.len(), 3);
+        assert!(matches!(p2p_rx.try_recv(), Ok(ChainMessage::Block(block)) if block.calculate_header_hash() == hash_2));
     }
 }
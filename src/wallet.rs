@@ -1,14 +1,97 @@
-use ed25519_dalek::{Signer, SigningKey, VerifyingKey, Signature, KeypairBytes};
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey, Signature, SECRET_KEY_LENGTH};
 use rand::rngs::OsRng;
 use std::fs;
 use std::path::Path;
 use crate::errors::NodeError;
+use ethers_core::types::Address;
+use ethers_core::utils::keccak256;
+use serde::{Deserialize, Serialize};
 
 pub struct Wallet {
     keypair: SigningKey,
 }
 
-// ... new(), sign(), public_key_hex(), load_or_create(), save(), verify_signature() functions remain the same ...
+/// The on-disk form of a wallet: the hex-encoded ed25519 secret key.
+#[derive(Serialize, Deserialize)]
+struct KeyFile {
+    secret_key: String,
+}
+
+impl Default for Wallet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Wallet {
+    /// Generates a wallet with a fresh random keypair.
+    pub fn new() -> Self {
+        Wallet { keypair: SigningKey::generate(&mut OsRng) }
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        self.keypair.sign(message)
+    }
+
+    pub fn public_key_hex(&self) -> String {
+        hex::encode(self.keypair.verifying_key().as_bytes())
+    }
+
+    /// Loads the wallet stored at `path`, or creates and saves a new one if the file does not exist.
+    pub fn load_or_create(path: &Path) -> Result<Self, NodeError> {
+        if !path.exists() {
+            let wallet = Self::new();
+            wallet.save(path)?;
+            return Ok(wallet);
+        }
+        let key_file: KeyFile = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let secret: [u8; SECRET_KEY_LENGTH] = hex::decode(&key_file.secret_key)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| NodeError::Config(format!("Invalid secret key in {}", path.display())))?;
+        Ok(Wallet { keypair: SigningKey::from_bytes(&secret) })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), NodeError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let key_file = KeyFile { secret_key: hex::encode(self.keypair.to_bytes()) };
+        fs::write(path, serde_json::to_string_pretty(&key_file)?)?;
+        Ok(())
+    }
+
+    /// Checks `signature` over `message` against a hex-encoded public key. Malformed keys fail verification.
+    pub fn verify_signature(pubkey_hex: &str, message: &[u8], signature: &Signature) -> bool {
+        let pubkey_array: [u8; 32] = match hex::decode(pubkey_hex).ok().and_then(|bytes| bytes.try_into().ok()) {
+            Some(arr) => arr,
+            None => return false,
+        };
+        let public_key = match VerifyingKey::from_bytes(&pubkey_array) {
+            Ok(key) => key,
+            Err(_) => return false,
+        };
+
+        public_key.verify_strict(message, signature).is_ok()
+    }
+
+    /// The on-chain account address of this wallet. See `address_from_public_key`.
+    pub fn address(&self) -> Address {
+        Self::address_from_bytes(self.keypair.verifying_key().as_bytes())
+    }
+
+    /// Derives the 20-byte account address owned by a hex-encoded ed25519 public key.
+    /// Like Ethereum, the address is the last 20 bytes of the keccak256 hash of the public key,
+    /// so the EVM `caller` and the key that signed a transaction always agree.
+    pub fn address_from_public_key(pubkey_hex: &str) -> Option<Address> {
+        let bytes: [u8; 32] = hex::decode(pubkey_hex).ok()?.try_into().ok()?;
+        Some(Self::address_from_bytes(&bytes))
+    }
+
+    fn address_from_bytes(pubkey: &[u8; 32]) -> Address {
+        Address::from_slice(&keccak256(pubkey)[12..])
+    }
+}
 
 // --- Unit Tests ---
 #[cfg(test)]
@@ -48,14 +131,13 @@ mod tests {
         let signature = wallet2.sign(message);
         assert!(Wallet::verify_signature(&pubkey2, message, &signature));
     }
-}            Ok(arr) => arr,
-            Err(_) => return false,
-        };
-        let public_key = match VerifyingKey::from_bytes(&pubkey_array) {
-            Ok(key) => key,
-            Err(_) => return false,
-        };
-        
-        public_key.verify_strict(message, signature).is_ok()
+
+    #[test]
+    fn test_address_matches_public_key() {
+        let wallet = Wallet::new();
+        let derived = Wallet::address_from_public_key(&wallet.public_key_hex());
+        assert_eq!(derived, Some(wallet.address()));
+        assert_ne!(wallet.address(), Wallet::new().address());
+        assert_eq!(Wallet::address_from_public_key("not hex"), None);
     }
 }
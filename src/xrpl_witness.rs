@@ -1,16 +1,17 @@
 use crate::config::WitnessConfig;
-use xrpl_rust::client::{Client, JsonRpcClient};
-use xrpl_rust::models::requests::{LedgerRequest, Subscribe};
-use xrpl_rust::models::streams::StreamMessage;
-use futures_util::StreamExt;
+use crate::errors::NodeError;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::sync::{Arc, Mutex};
 use tokio::time::{sleep, Duration};
 
+/// How often the witness polls the XRPL node for new ledgers and door account payments.
+const POLL_INTERVAL_SECONDS: u64 = 15;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DepositInfo {
-    pub from: String,       
-    pub amount: String,     
+    pub from: String,
+    pub amount: String,
     pub l2_destination: u64,
 }
 
@@ -22,16 +23,52 @@ struct PendingDeposit {
 
 type PendingQueue = Arc<Mutex<Vec<PendingDeposit>>>;
 
+/// A minimal client for the rippled JSON-RPC methods the witness needs.
+struct XrplClient {
+    http: reqwest::Client,
+    url: String,
+}
+
+impl XrplClient {
+    async fn call(&self, method: &str, params: Value) -> Result<Value, NodeError> {
+        let body = json!({ "method": method, "params": [params] });
+        let response: Value = self.http.post(&self.url).json(&body).send().await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| NodeError::Witness(format!("{} request failed: {}", method, e)))?
+            .json().await
+            .map_err(|e| NodeError::Witness(format!("Invalid {} response: {}", method, e)))?;
+        let result = response.get("result").cloned()
+            .ok_or_else(|| NodeError::Witness(format!("{} response without a result", method)))?;
+        if result.get("status").and_then(Value::as_str) == Some("error") {
+            return Err(NodeError::Witness(format!("{} failed: {}", method, result["error"])));
+        }
+        Ok(result)
+    }
+
+    async fn current_ledger_index(&self) -> Result<u32, NodeError> {
+        let result = self.call("ledger_current", json!({})).await?;
+        result["ledger_current_index"].as_u64()
+            .and_then(|index| u32::try_from(index).ok())
+            .ok_or_else(|| NodeError::Witness("Missing ledger_current_index".into()))
+    }
+
+    /// The validated transactions of `account` in ledgers `from_ledger` and later, oldest first.
+    async fn account_transactions(&self, account: &str, from_ledger: u32) -> Result<Vec<Value>, NodeError> {
+        let params = json!({
+            "account": account,
+            "ledger_index_min": from_ledger,
+            "ledger_index_max": -1,
+            "forward": true,
+        });
+        let result = self.call("account_tx", params).await?;
+        Ok(result["transactions"].as_array().cloned().unwrap_or_default())
+    }
+}
+
 pub async fn run_xrpl_witness(config: WitnessConfig) {
     println!("[XRPL Witness] Starting service...");
-    let client = match JsonRpcClient::new(config.xrpl_node_url.parse().unwrap()) {
-        Ok(c) => Arc::new(c),
-        Err(e) => {
-            eprintln!("[XRPL Witness] Failed to create client: {}", e);
-            return;
-        }
-    };
-    
+    let client = Arc::new(XrplClient { http: reqwest::Client::new(), url: config.xrpl_node_url.clone() });
+
     let pending_queue: PendingQueue = Arc::new(Mutex::new(Vec::new()));
 
     let listener_client = Arc::clone(&client);
@@ -44,21 +81,37 @@ pub async fn run_xrpl_witness(config: WitnessConfig) {
     tokio::spawn(process_confirmed_deposits(processor_client, processor_queue, config.confirmation_threshold));
 }
 
-async fn listen_for_deposits(client: Arc<JsonRpcClient>, queue: PendingQueue, door_account: String) {
-    let sub = Subscribe::new().accounts(vec![door_account.clone()]).build().unwrap();
-    let mut stream = match client.subscribe(&sub).await {
-        Ok(s) => s,
-        Err(e) => {
-            eprintln!("[XRPL Witness] Failed to subscribe: {}", e);
-            return;
+async fn listen_for_deposits(client: Arc<XrplClient>, queue: PendingQueue, door_account: String) {
+    // Only payments made after the witness started are of interest.
+    let mut next_ledger = loop {
+        match client.current_ledger_index().await {
+            Ok(index) => break index,
+            Err(e) => {
+                eprintln!("[XRPL Witness] Failed to reach XRPL node: {}", e);
+                sleep(Duration::from_secs(POLL_INTERVAL_SECONDS)).await;
+            }
         }
     };
-    println!("[XRPL Witness] Subscribed to door account: {}", door_account);
+    println!("[XRPL Witness] Watching door account {} from ledger {}", door_account, next_ledger);
 
-    while let Some(msg) = stream.next().await {
-        if let Ok(StreamMessage::Transaction(tx)) = msg {
-            if let Some(info) = parse_deposit_transaction(&tx, &door_account) {
-                let ledger_index = tx.common().ledger_index.unwrap_or(0);
+    loop {
+        sleep(Duration::from_secs(POLL_INTERVAL_SECONDS)).await;
+
+        let transactions = match client.account_transactions(&door_account, next_ledger).await {
+            Ok(transactions) => transactions,
+            Err(e) => {
+                eprintln!("[XRPL Witness] Failed to fetch door account transactions: {}", e);
+                continue;
+            }
+        };
+
+        for entry in transactions {
+            let tx = &entry["tx"];
+            let Some(ledger_index) = tx["ledger_index"].as_u64().and_then(|index| u32::try_from(index).ok()) else {
+                continue;
+            };
+            next_ledger = next_ledger.max(ledger_index + 1);
+            if let Some(info) = parse_deposit_transaction(tx, &door_account) {
                 println!("[XRPL Witness] Saw potential deposit in ledger {}. Adding to pending queue.", ledger_index);
                 let deposit = PendingDeposit { info, seen_in_ledger: ledger_index };
                 queue.lock().unwrap().push(deposit);
@@ -67,12 +120,12 @@ async fn listen_for_deposits(client: Arc<JsonRpcClient>, queue: PendingQueue, do
     }
 }
 
-async fn process_confirmed_deposits(client: Arc<JsonRpcClient>, queue: PendingQueue, confirmation_threshold: u32) {
+async fn process_confirmed_deposits(client: Arc<XrplClient>, queue: PendingQueue, confirmation_threshold: u32) {
     loop {
-        sleep(Duration::from_secs(15)).await;
+        sleep(Duration::from_secs(POLL_INTERVAL_SECONDS)).await;
 
-        let current_ledger_index = match client.ledger(LedgerRequest::current()).await {
-            Ok(resp) => resp.ledger_index,
+        let current_ledger_index = match client.current_ledger_index().await {
+            Ok(index) => index,
             Err(_) => continue,
         };
 
@@ -97,19 +150,46 @@ async fn process_confirmed_deposits(client: Arc<JsonRpcClient>, queue: PendingQu
     }
 }
 
-fn parse_deposit_transaction(tx: &xrpl_rust::models::transactions::Transaction, door_account: &str) -> Option<DepositInfo> {
-    if let xrpl_rust::models::transactions::Transaction::Payment(payment) = tx {
-        if payment.destination.to_string() == door_account {
-            if let (Some(dest_tag), Some(sender)) = (payment.destination_tag, &payment.common.account) {
-                if let xrpl_rust::models::Amount::Xrp(amount) = &payment.amount {
-                    return Some(DepositInfo {
-                        from: sender.to_string(),
-                        amount: amount.to_string(),
-                        l2_destination: dest_tag,
-                    });
-                }
-            }
-        }
+/// Recognizes an XRP payment to the door account. The destination tag names the L2 recipient.
+fn parse_deposit_transaction(tx: &Value, door_account: &str) -> Option<DepositInfo> {
+    if tx["TransactionType"].as_str()? != "Payment" || tx["Destination"].as_str()? != door_account {
+        return None;
+    }
+    // Issued currency amounts are objects; XRP amounts are a string of drops.
+    let amount = tx["Amount"].as_str()?;
+    Some(DepositInfo {
+        from: tx["Account"].as_str()?.to_string(),
+        amount: amount.to_string(),
+        l2_destination: tx["DestinationTag"].as_u64()?,
+    })
+}
+
+// --- Unit Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_tagged_xrp_payments_to_the_door_are_deposits() {
+        let door = "rDoor";
+        let payment = json!({
+            "TransactionType": "Payment",
+            "Account": "rSender",
+            "Destination": door,
+            "DestinationTag": 42,
+            "Amount": "1000000",
+        });
+        let deposit = parse_deposit_transaction(&payment, door).unwrap();
+        assert_eq!((deposit.from.as_str(), deposit.amount.as_str(), deposit.l2_destination), ("rSender", "1000000", 42));
+
+        let mut untagged = payment.clone();
+        untagged.as_object_mut().unwrap().remove("DestinationTag");
+        assert!(parse_deposit_transaction(&untagged, door).is_none());
+
+        let mut issued = payment.clone();
+        issued["Amount"] = json!({ "currency": "USD", "issuer": "rIssuer", "value": "1" });
+        assert!(parse_deposit_transaction(&issued, door).is_none());
+
+        assert!(parse_deposit_transaction(&payment, "rOther").is_none());
     }
-    None
 }
@@ -1,9 +1,29 @@
 use assert_fs::prelude::*;
 use assert_fs::TempDir;
-use std::process::Command;
+use std::path::Path;
+use std::process::{Command, Output};
 use std::thread::sleep;
 use std::time::Duration;
-use kosher_chain::block::Transaction; // Assuming your crate is named kosher_chain
+
+/// Runs the node binary with the given config and arguments, failing the test if it exits with an error.
+fn kosher_chain(config: &Path, args: &[&str]) -> String {
+    let Output { status, stdout, stderr } = Command::new(env!("CARGO_BIN_EXE_kosher_chain"))
+        .arg("--config")
+        .arg(config)
+        .args(args)
+        .output()
+        .expect("Failed to run kosher_chain");
+    assert!(status.success(), "kosher_chain {:?} failed: {}", args, String::from_utf8_lossy(&stderr));
+    String::from_utf8(stdout).unwrap()
+}
+
+/// The value printed after `label` by a command, e.g. the address printed by `keys generate`.
+fn field<'a>(output: &'a str, label: &str) -> &'a str {
+    output.lines()
+        .find_map(|line| line.trim().strip_prefix(label))
+        .map(str::trim)
+        .unwrap_or_else(|| panic!("No {} in output: {}", label, output))
+}
 
 #[test]
 #[ignore] // Ignored by default as it's a long-running test
@@ -11,57 +31,69 @@ fn test_node_receives_transaction_and_creates_block() {
     // 1. Set up a temporary directory for all test files
     let temp = TempDir::new().unwrap();
     let config_path = temp.child("config.toml");
-    let validators_path = temp.child("validators.json");
-    let key_path = temp.child("validator_key.json");
-    let state_path = temp.child("blockchain_state.json");
+    let genesis_path = temp.child("genesis.json");
+    let validator_key = temp.child("validator_key.json");
+    let user_key = temp.child("user_key.json");
+    let tx_path = temp.child("tx.json");
+    let data_dir = temp.child("data");
 
-    // 2. Create the validator key
-    // In a real test, we would run a command or use the wallet code to generate this.
-    // For now, we assume it's pre-generated and its public key is known.
-    let validator_pubkey = "your_known_validator_public_key_hex";
-
-    // 3. Create config files
-    validators_path.write_str(&format!(r#"{{"validators": ["{}"]}}"#, validator_pubkey)).unwrap();
     config_path.write_str(&format!(r#"
         [api]
-        listen_address = "127.0.0.1:8080"
+        listen_address = "127.0.0.1:38080"
         [p2p]
-        listen_address = "/ip4/0.0.0.0/tcp/0"
+        listen_address = "/ip4/127.0.0.1/tcp/0"
         [witness]
-        xrpl_node_url = "wss://s1.ripple.com:51234"
+        xrpl_node_url = "http://127.0.0.1:1"
         door_account = "r..."
         confirmation_threshold = 10
         [chain]
-        validators_file = "{}"
+        genesis_file = "{}"
         [validator]
         key_file = "{}"
-    "#, validators_path.path().to_str().unwrap(), key_path.path().to_str().unwrap())).unwrap();
+        [storage]
+        data_dir = "{}"
+    "#, genesis_path.path().display(), validator_key.path().display(), data_dir.path().display())).unwrap();
+    let config = config_path.path();
+
+    // 2. Create the keys and a genesis that funds the user
+    let validator = kosher_chain(config, &["keys", "generate", "--key-file", validator_key.path().to_str().unwrap()]);
+    let user = kosher_chain(config, &["keys", "generate", "--key-file", user_key.path().to_str().unwrap()]);
+    let user_address = field(&user, "Address:");
+    kosher_chain(config, &[
+        "genesis", "init",
+        "--validator", field(&validator, "Public key:"),
+        "--alloc", &format!("{}=1000000", user_address),
+    ]);
 
-    // 4. Run the node binary in the background
-    let mut node_process = Command::new("cargo")
-        .args(&["run", "--release"])
-        .current_dir(temp.path()) // Run from the temp directory
+    // 3. Run the node in the background
+    let mut node_process = Command::new(env!("CARGO_BIN_EXE_kosher_chain"))
+        .arg("--config")
+        .arg(config)
         .spawn()
         .expect("Failed to start node process");
-        
     sleep(Duration::from_secs(5)); // Wait for the node to start up
 
-    // 5. Send a transaction to the API
-    let client = reqwest::blocking::Client::new();
-    let sample_tx: Transaction = // create a valid, signed transaction here
-    
-    let res = client.post("http://127.0.0.1:8080/transaction")
-        .json(&sample_tx)
-        .send()
+    // 4. Send a transfer to the API
+    let recipient = "0x00000000000000000000000000000000000000aa";
+    let tx = kosher_chain(config, &[
+        "tx", "sign", "--key-file", user_key.path().to_str().unwrap(), "--to", recipient, "--value", "250", "--nonce", "0",
+    ]);
+    tx_path.write_str(&tx).unwrap();
+    let sent = Command::new(env!("CARGO_BIN_EXE_kosher_chain"))
+        .args(["--config", config.to_str().unwrap(), "tx", "send", "--file", tx_path.path().to_str().unwrap(), "--api", "http://127.0.0.1:38080"])
+        .status()
         .unwrap();
-    assert!(res.status().is_success());
 
-    // 6. Wait for the validator to propose a block
+    // 5. Wait for the validator to propose and commit a block
     sleep(Duration::from_secs(20));
 
-    // 7. Shut down the node and verify the state
+    // 6. Shut down the node and verify the state
     node_process.kill().unwrap();
-    
-    let state_content = state_path.read_to_string().unwrap();
-    assert!(state_content.contains(&sample_tx.hash));
+    node_process.wait().unwrap();
+    assert!(sent.success());
+
+    let recipient_state = kosher_chain(config, &["state", "inspect", recipient]);
+    assert_eq!(field(&recipient_state, "Balance:"), "250");
+    let user_state = kosher_chain(config, &["state", "inspect", user_address]);
+    assert_eq!(field(&user_state, "Nonce:"), "1");
 }